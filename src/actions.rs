@@ -0,0 +1,351 @@
+use std::fmt;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::engine::{Detection, Error, MetaValue, RuleMatch};
+use crate::options::Options;
+use crate::quarantine::Quarantine;
+use crate::report::Reporter;
+
+// how often to check if a command started by an exec action has exited
+const EXEC_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// name of the rule metadata used by severity selectors
+const SEVERITY_META: &str = "severity";
+
+/// What to do with a detected file, in the order actions are executed.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Action {
+    /// Only report the detection.
+    Log,
+    /// Run a command with the path and the matched rule names as arguments.
+    Exec(String),
+    /// Move the file to the quarantine.
+    Quarantine,
+    /// Delete the file.
+    Delete,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Log => write!(f, "log"),
+            Action::Exec(command) => write!(f, "exec:{}", command),
+            Action::Quarantine => write!(f, "quarantine"),
+            Action::Delete => write!(f, "delete"),
+        }
+    }
+}
+
+/// Which detections an action applies to.
+#[derive(Clone, Debug)]
+pub enum Selector {
+    /// Every detection.
+    Any,
+    /// Rule identifier, optionally prefixed by its namespace.
+    Rule(String),
+    Tag(String),
+    /// Value of the rule 'severity' metadata.
+    Severity(String),
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Selector::Any => write!(f, "any"),
+            Selector::Rule(name) => write!(f, "rule:{}", name),
+            Selector::Tag(tag) => write!(f, "tag:{}", tag),
+            Selector::Severity(severity) => write!(f, "severity:{}", severity),
+        }
+    }
+}
+
+impl Selector {
+    fn matches(&self, rule: &RuleMatch) -> bool {
+        match self {
+            Selector::Any => true,
+            Selector::Rule(name) => {
+                *name == rule.identifier
+                    || *name == format!("{}:{}", &rule.namespace, &rule.identifier)
+            }
+            Selector::Tag(tag) => rule.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)),
+            Selector::Severity(severity) => rule.metadata.iter().any(|(key, value)| {
+                key.eq_ignore_ascii_case(SEVERITY_META)
+                    && match value {
+                        MetaValue::String(s) => s.eq_ignore_ascii_case(severity),
+                        MetaValue::Integer(i) => i.to_string() == *severity,
+                        MetaValue::Boolean(_) => false,
+                    }
+            }),
+        }
+    }
+}
+
+/// The result of running an action on a detected file.
+#[derive(Clone, Debug)]
+pub struct Outcome {
+    pub action: String,
+    pub success: bool,
+    pub message: String,
+}
+
+/// Runs the configured actions for each detection.
+pub(crate) struct Actions {
+    bindings: Vec<(Selector, Action)>,
+    quarantine: Option<Quarantine>,
+    timeout: Duration,
+}
+
+/// Parses a '[rule:NAME|tag:NAME|severity:LEVEL=]log|delete|quarantine|exec:COMMAND' specification.
+pub fn parse_action(spec: &str) -> Result<(Selector, Action), Error> {
+    let (selector, action) =
+        if spec.starts_with("rule:") || spec.starts_with("tag:") || spec.starts_with("severity:") {
+            match spec.split_once('=') {
+                Some((selector, action)) => (selector, action),
+                None => return Err(format!("missing action in '{}'", spec)),
+            }
+        } else {
+            ("", spec)
+        };
+
+    let selector = match selector.split_once(':') {
+        None => Selector::Any,
+        Some(("rule", name)) => Selector::Rule(name.to_string()),
+        Some(("tag", tag)) => Selector::Tag(tag.to_string()),
+        Some((_, severity)) => Selector::Severity(severity.to_string()),
+    };
+
+    let action = match action {
+        "log" => Action::Log,
+        "quarantine" => Action::Quarantine,
+        "delete" => Action::Delete,
+        _ => match action.strip_prefix("exec:") {
+            Some(command) if !command.trim().is_empty() => Action::Exec(command.to_string()),
+            _ => return Err(format!("invalid action '{}' in '{}'", action, spec)),
+        },
+    };
+
+    Ok((selector, action))
+}
+
+impl Actions {
+    pub fn from_options(options: &Options) -> Result<Self, String> {
+        let bindings = options.actions.clone();
+
+        for (selector, action) in &bindings {
+            log::info!("action {} for {:?}", action, selector);
+        }
+
+        let quarantine = if bindings.iter().any(|(_, a)| *a == Action::Quarantine) {
+            Some(Quarantine::new(&options.quarantine_dir)?)
+        } else {
+            None
+        };
+
+        Ok(Actions {
+            bindings,
+            quarantine,
+            timeout: options.action_timeout,
+        })
+    }
+
+    /// Runs the actions matching this detection and reports their outcomes.
+    pub fn run(&self, detection: &Detection, reporter: &dyn Reporter) {
+        let mut actions: Vec<&Action> = self
+            .bindings
+            .iter()
+            .filter(|(selector, _)| {
+                detection
+                    .all_matches()
+                    .into_iter()
+                    .any(|r| selector.matches(r))
+            })
+            .map(|(_, action)| action)
+            .collect();
+
+        actions.sort();
+        actions.dedup();
+
+        // set once the file has been moved or deleted
+        let mut gone = false;
+
+        for action in actions {
+            let res = match action {
+                Action::Log => Ok("logged".to_string()),
+                Action::Exec(command) => self.exec(command, detection),
+                Action::Quarantine | Action::Delete if detection.process.is_some() => {
+                    Ok("skipped, not a file".to_string())
+                }
+                Action::Quarantine | Action::Delete if gone => {
+                    Ok("skipped, file already removed".to_string())
+                }
+                Action::Quarantine => self.quarantine(detection),
+                Action::Delete => std::fs::remove_file(&detection.path)
+                    .map(|_| "deleted".to_string())
+                    .map_err(|e| format!("can't delete {:?}: {:?}", &detection.path, e)),
+            };
+
+            if res.is_ok() && matches!(action, Action::Quarantine | Action::Delete) {
+                gone = true;
+            }
+
+            reporter.action(
+                detection,
+                &Outcome {
+                    action: action.to_string(),
+                    success: res.is_ok(),
+                    message: res.unwrap_or_else(|e| e),
+                },
+            );
+        }
+    }
+
+    fn quarantine(&self, detection: &Detection) -> Result<String, String> {
+        match &self.quarantine {
+            Some(quarantine) => quarantine
+                .quarantine(detection)
+                .map(|record| format!("moved to quarantine as {}", record.id)),
+            None => Err("quarantine not initialized".to_string()),
+        }
+    }
+
+    fn exec(&self, command: &str, detection: &Detection) -> Result<String, String> {
+        let mut parts = command.split_whitespace();
+        let program = parts.next().unwrap_or_default();
+        let rules: Vec<String> = detection
+            .all_matches()
+            .into_iter()
+            .map(|r| format!("{}:{}", &r.namespace, &r.identifier))
+            .collect();
+
+        let mut child = Command::new(program)
+            .args(parts)
+            .arg(&detection.path)
+            .args(&rules)
+            .env("SAURON_PATH", &detection.path)
+            .env("SAURON_RULES", rules.join(","))
+            .envs(
+                detection
+                    .process
+                    .as_ref()
+                    .map(|p| ("SAURON_PID", p.pid.to_string())),
+            )
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("can't execute '{}': {:?}", command, e))?;
+
+        let start = Instant::now();
+        loop {
+            match child.try_wait() {
+                Ok(Some(status)) if status.success() => {
+                    return Ok(format!("'{}' {}", command, status))
+                }
+                Ok(Some(status)) => return Err(format!("'{}' {}", command, status)),
+                Ok(None) if start.elapsed() >= self.timeout => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(format!("'{}' killed after {:?}", command, self.timeout));
+                }
+                Ok(None) => thread::sleep(EXEC_POLL_INTERVAL),
+                Err(e) => return Err(format!("can't wait for '{}': {:?}", command, e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule() -> RuleMatch {
+        RuleMatch {
+            namespace: "malware".to_string(),
+            identifier: "evil".to_string(),
+            tags: vec!["Ransomware".to_string()],
+            metadata: vec![
+                (
+                    "Severity".to_string(),
+                    MetaValue::String("High".to_string()),
+                ),
+                ("score".to_string(), MetaValue::Integer(80)),
+            ],
+            strings: vec![],
+        }
+    }
+
+    #[test]
+    fn parses_actions() {
+        assert!(matches!(
+            parse_action("log").unwrap(),
+            (Selector::Any, Action::Log)
+        ));
+        assert!(matches!(
+            parse_action("rule:malware:evil=quarantine").unwrap(),
+            (Selector::Rule(name), Action::Quarantine) if name == "malware:evil"
+        ));
+        assert!(matches!(
+            parse_action("tag:ransomware=delete").unwrap(),
+            (Selector::Tag(tag), Action::Delete) if tag == "ransomware"
+        ));
+        assert!(matches!(
+            parse_action("severity:high=exec:/bin/alert --now").unwrap(),
+            (Selector::Severity(severity), Action::Exec(command))
+                if severity == "high" && command == "/bin/alert --now"
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_actions() {
+        for spec in [
+            "",
+            "kill",
+            "exec:",
+            "exec: ",
+            "tag:x",
+            "tag:x=kill",
+            "rule:x=exec:",
+        ] {
+            assert!(parse_action(spec).is_err(), "{:?} was accepted", spec);
+        }
+    }
+
+    #[test]
+    fn selectors_match_rules() {
+        let rule = rule();
+        let selects = |spec: &str| parse_action(spec).unwrap().0.matches(&rule);
+
+        assert!(selects("log"));
+        assert!(selects("rule:evil=log"));
+        assert!(selects("rule:malware:evil=log"));
+        assert!(!selects("rule:other:evil=log"));
+        assert!(!selects("rule:evi=log"));
+        assert!(selects("tag:ransomware=log"));
+        assert!(!selects("tag:worm=log"));
+        assert!(selects("severity:high=log"));
+        assert!(!selects("severity:low=log"));
+        assert!(!selects("severity:80=log"));
+    }
+
+    #[test]
+    fn severity_matches_integers() {
+        let mut rule = rule();
+        rule.metadata = vec![("severity".to_string(), MetaValue::Integer(3))];
+
+        assert!(Selector::Severity("3".to_string()).matches(&rule));
+        assert!(!Selector::Severity("4".to_string()).matches(&rule));
+    }
+
+    #[test]
+    fn formats_selectors_as_specs() {
+        for spec in [
+            "rule:evil=log",
+            "tag:x=delete",
+            "severity:high=exec:/bin/true",
+        ] {
+            let (selector, action) = parse_action(spec).unwrap();
+            assert_eq!(format!("{}={}", selector, action), spec);
+        }
+    }
+}
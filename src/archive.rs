@@ -0,0 +1,408 @@
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use bzip2::read::BzDecoder;
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+
+use crate::engine::{Detection, Engine};
+use crate::hashes::Hashes;
+
+// offset and value of the magic identifying tar archives
+const TAR_MAGIC_OFFSET: usize = 257;
+const TAR_MAGIC: &[u8] = b"ustar";
+
+// separates the path of an archive from the path of a file inside it
+const MEMBER_SEPARATOR: &str = "!/";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Zip,
+    Tar,
+    Gzip,
+    Bzip2,
+}
+
+impl Kind {
+    fn detect(header: &[u8]) -> Option<Self> {
+        if header.starts_with(b"PK\x03\x04") {
+            Some(Kind::Zip)
+        } else if header.starts_with(b"\x1f\x8b") {
+            Some(Kind::Gzip)
+        } else if header.starts_with(b"BZh") {
+            Some(Kind::Bzip2)
+        } else if header.len() >= TAR_MAGIC_OFFSET + TAR_MAGIC.len()
+            && &header[TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + TAR_MAGIC.len()] == TAR_MAGIC
+        {
+            Some(Kind::Tar)
+        } else {
+            None
+        }
+    }
+}
+
+/// Bounds on the extraction of archives, protecting against archive bombs.
+#[derive(Clone, Debug)]
+pub struct Limits {
+    /// How many levels of archives inside archives are extracted.
+    pub max_depth: usize,
+    /// Total amount of uncompressed bytes extracted from an archive.
+    pub max_size: u64,
+    /// Total number of files extracted from an archive.
+    pub max_members: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_depth: 3,
+            max_size: 256 * 1024 * 1024,
+            max_members: 10000,
+        }
+    }
+}
+
+// extraction state of a top level archive
+struct Extractor<'a> {
+    engine: &'a Engine,
+    limits: &'a Limits,
+    extracted_size: u64,
+    extracted_members: usize,
+    detections: Vec<Detection>,
+    errors: Vec<(PathBuf, String)>,
+}
+
+/// Detections and errors for the files inside an archive.
+pub(crate) struct Members {
+    pub detections: Vec<Detection>,
+    pub errors: Vec<(PathBuf, String)>,
+}
+
+/// If the file is an archive, extracts it in memory and scans each file inside it.
+pub(crate) fn scan(engine: &Engine, path: &Path, limits: &Limits) -> Members {
+    let mut extractor = Extractor {
+        engine,
+        limits,
+        extracted_size: 0,
+        extracted_members: 0,
+        detections: vec![],
+        errors: vec![],
+    };
+
+    let label = path.to_string_lossy();
+    match File::open(path) {
+        Ok(file) => {
+            if let Err(e) = extractor.visit(file, &label, 0) {
+                extractor.errors.push((path.to_path_buf(), e));
+            }
+        }
+        Err(e) => extractor.errors.push((
+            path.to_path_buf(),
+            format!("can't open {:?}: {:?}", path, e),
+        )),
+    }
+
+    Members {
+        detections: extractor.detections,
+        errors: extractor.errors,
+    }
+}
+
+impl<'a> Extractor<'a> {
+    fn visit<R: Read + Seek>(
+        &mut self,
+        mut reader: R,
+        label: &str,
+        depth: usize,
+    ) -> Result<(), String> {
+        let mut header = vec![0u8; TAR_MAGIC_OFFSET + TAR_MAGIC.len()];
+        let read = read_header(&mut reader, &mut header).map_err(|e| e.to_string())?;
+        reader.seek(SeekFrom::Start(0)).map_err(|e| e.to_string())?;
+
+        match Kind::detect(&header[..read]) {
+            Some(Kind::Zip) => self.zip(reader, label, depth),
+            Some(Kind::Tar) => self.tar(reader, label, depth),
+            Some(Kind::Gzip) => self.compressed(GzDecoder::new(reader), label, depth),
+            Some(Kind::Bzip2) => self.compressed(BzDecoder::new(reader), label, depth),
+            None => Ok(()),
+        }
+    }
+
+    fn zip<R: Read + Seek>(&mut self, reader: R, label: &str, depth: usize) -> Result<(), String> {
+        let mut archive =
+            ZipArchive::new(reader).map_err(|e| format!("can't read {}: {:?}", label, e))?;
+
+        for index in 0..archive.len() {
+            let mut file = archive
+                .by_index(index)
+                .map_err(|e| format!("can't read {}: {:?}", label, e))?;
+            if file.is_dir() {
+                continue;
+            }
+
+            let name = format!("{}{}{}", label, MEMBER_SEPARATOR, file.name());
+            let data = self.extract(&mut file, &name)?;
+
+            drop(file);
+
+            self.member(data, name, depth);
+        }
+
+        Ok(())
+    }
+
+    fn tar<R: Read>(&mut self, reader: R, label: &str, depth: usize) -> Result<(), String> {
+        let mut archive = tar::Archive::new(reader);
+        let entries = archive
+            .entries()
+            .map_err(|e| format!("can't read {}: {:?}", label, e))?;
+
+        for entry in entries {
+            let mut entry = entry.map_err(|e| format!("can't read {}: {:?}", label, e))?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+
+            let member_path = entry
+                .path()
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let name = format!("{}{}{}", label, MEMBER_SEPARATOR, member_path);
+            let data = self.extract(&mut entry, &name)?;
+
+            self.member(data, name, depth);
+        }
+
+        Ok(())
+    }
+
+    // gzip and bzip2 compress a single file, which is often a tar archive
+    fn compressed<R: Read>(&mut self, decoder: R, label: &str, depth: usize) -> Result<(), String> {
+        let data = self.extract(decoder, label)?;
+
+        let kind = Kind::detect(&data[..data.len().min(TAR_MAGIC_OFFSET + TAR_MAGIC.len())]);
+        if kind == Some(Kind::Tar) {
+            self.tar(Cursor::new(data), label, depth)
+        } else {
+            // name the decompressed file after the archive without its extension
+            let file_name = label.rsplit(MEMBER_SEPARATOR).next().unwrap_or(label);
+            let stem = Path::new(file_name)
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let name = format!("{}{}{}", label, MEMBER_SEPARATOR, stem);
+
+            self.member(data, name, depth);
+
+            Ok(())
+        }
+    }
+
+    // read a file out of an archive, enforcing the limits
+    fn extract<R: Read>(&mut self, reader: R, label: &str) -> Result<Vec<u8>, String> {
+        self.extracted_members += 1;
+        if self.extracted_members > self.limits.max_members {
+            return Err(format!(
+                "{}: more than {} files in archive, stopping extraction",
+                label, self.limits.max_members
+            ));
+        }
+
+        let remaining = self.limits.max_size.saturating_sub(self.extracted_size);
+        let mut data = vec![];
+
+        reader
+            .take(remaining + 1)
+            .read_to_end(&mut data)
+            .map_err(|e| format!("can't extract {}: {:?}", label, e))?;
+
+        if data.len() as u64 > remaining {
+            return Err(format!(
+                "{}: more than {} uncompressed bytes in archive, stopping extraction",
+                label, self.limits.max_size
+            ));
+        }
+
+        self.extracted_size += data.len() as u64;
+
+        Ok(data)
+    }
+
+    // scan a file extracted from an archive and, if it's an archive itself, what's inside it
+    fn member(&mut self, data: Vec<u8>, name: String, depth: usize) {
+        let mut detection = self.engine.scan_bytes(&data, &name);
+
+        if let Some(error) = detection.error.take() {
+            self.errors.push((detection.path, error));
+        } else if detection.detected {
+            detection.hashes = Some(Hashes::of_bytes(&data));
+            self.detections.push(detection);
+        }
+
+        if depth + 1 < self.limits.max_depth {
+            if let Err(e) = self.visit(Cursor::new(data), &name, depth + 1) {
+                self.errors.push((PathBuf::from(name), e));
+            }
+        }
+    }
+}
+
+// fill as much of the header buffer as possible, returning the amount of bytes read
+fn read_header<R: Read>(reader: &mut R, header: &mut [u8]) -> std::io::Result<usize> {
+    let mut read = 0;
+    while read < header.len() {
+        match reader.read(&mut header[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    Ok(read)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use zip::write::{FileOptions, ZipWriter};
+
+    use crate::engine::test_engine;
+
+    const RULES: &str = r#"rule evil { strings: $a = "EVILSTRING" condition: $a }"#;
+
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        for (name, data) in files {
+            writer.start_file(*name, FileOptions::default()).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    // extract in memory data like scan does with a file
+    fn extract(data: Vec<u8>, label: &str, limits: &Limits) -> Members {
+        let engine = test_engine(RULES);
+        let mut extractor = Extractor {
+            engine: &engine,
+            limits,
+            extracted_size: 0,
+            extracted_members: 0,
+            detections: vec![],
+            errors: vec![],
+        };
+
+        if let Err(e) = extractor.visit(Cursor::new(data), label, 0) {
+            extractor.errors.push((PathBuf::from(label), e));
+        }
+
+        Members {
+            detections: extractor.detections,
+            errors: extractor.errors,
+        }
+    }
+
+    fn names(members: &Members) -> Vec<String> {
+        members
+            .detections
+            .iter()
+            .map(|d| d.path.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn scans_members() {
+        let data = zip(&[("clean.txt", b"hello"), ("evil.txt", b"xxEVILSTRINGxx")]);
+        let members = extract(data, "test.zip", &Limits::default());
+
+        assert!(members.errors.is_empty());
+        assert_eq!(names(&members), vec!["test.zip!/evil.txt"]);
+        assert!(members.detections[0].hashes.is_some());
+    }
+
+    #[test]
+    fn limits_depth() {
+        let inner = zip(&[("evil.txt", b"EVILSTRING")]);
+        let middle = zip(&[("inner.zip", &inner)]);
+        let outer = zip(&[("middle.zip", &middle)]);
+
+        let limits = |max_depth| Limits {
+            max_depth,
+            ..Limits::default()
+        };
+
+        let members = extract(outer.clone(), "test.zip", &limits(3));
+        assert_eq!(
+            names(&members),
+            vec!["test.zip!/middle.zip!/inner.zip!/evil.txt"]
+        );
+
+        let members = extract(outer, "test.zip", &limits(2));
+        assert!(members.detections.is_empty());
+        assert!(members.errors.is_empty());
+    }
+
+    #[test]
+    fn limits_members() {
+        let data = zip(&[
+            ("1.txt", b"EVILSTRING"),
+            ("2.txt", b"EVILSTRING"),
+            ("3.txt", b"EVILSTRING"),
+        ]);
+        let limits = Limits {
+            max_members: 2,
+            ..Limits::default()
+        };
+        let members = extract(data, "test.zip", &limits);
+
+        assert_eq!(names(&members), vec!["test.zip!/1.txt", "test.zip!/2.txt"]);
+        assert_eq!(members.errors.len(), 1);
+        assert!(members.errors[0].1.contains("more than 2 files"));
+    }
+
+    #[test]
+    fn limits_uncompressed_size() {
+        // a few KB of zeros decompressing to 16M, with the match at the end
+        let mut bomb = vec![0u8; 16 * 1024 * 1024];
+        bomb.extend_from_slice(b"EVILSTRING");
+        let data = zip(&[("bomb.bin", &bomb)]);
+        assert!(data.len() < 1024 * 1024);
+
+        let limits = Limits {
+            max_size: 1024 * 1024,
+            ..Limits::default()
+        };
+        let members = extract(data, "test.zip", &limits);
+
+        assert!(members.detections.is_empty());
+        assert_eq!(members.errors.len(), 1);
+        assert!(members.errors[0].1.contains("uncompressed bytes"));
+    }
+
+    #[test]
+    fn counts_size_across_members() {
+        let data = zip(&[("1.bin", &[0u8; 600]), ("2.bin", &[0u8; 600])]);
+        let limits = Limits {
+            max_size: 1000,
+            ..Limits::default()
+        };
+        let members = extract(data, "test.zip", &limits);
+
+        assert_eq!(members.errors.len(), 1);
+        assert!(members.errors[0].1.starts_with("test.zip!/2.bin"));
+    }
+
+    #[test]
+    fn decompresses_gzip() {
+        let members = extract(gzip(b"EVILSTRING"), "evil.txt.gz", &Limits::default());
+        assert_eq!(names(&members), vec!["evil.txt.gz!/evil.txt"]);
+    }
+}
@@ -0,0 +1,591 @@
+use std::time::Duration;
+
+use clap::{Args, FromArgMatches, Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+
+use sauron::report::OutputFormat;
+use sauron::{
+    parse_action, Action, Limits, MetaValue, NamespaceMode, Options, Selector, BUILTIN_EXTERNALS,
+    DEFAULT_QUARANTINE_DIR,
+};
+
+#[derive(Parser, Debug)]
+#[clap(
+    about = "Minimalistic cross-platform filesystem monitor and malware scanner using YARA rules."
+)]
+pub(crate) struct Cli {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+    /// Load settings from this TOML file (/etc/sauron/sauron.toml if it exists by default), command line arguments take precedence.
+    #[clap(long, global = true)]
+    pub config: Option<String>,
+    #[clap(
+        flatten,
+        next_help_heading = "DEPRECATED OPTIONS (use a command instead)"
+    )]
+    pub legacy: LegacyArgs,
+}
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum Command {
+    /// Scan the files in the root folder, or the memory of the running processes, and exit.
+    Scan(ScanCommand),
+    /// Monitor the root folder and scan new and modified files.
+    Monitor(MonitorCommand),
+    /// Serve a local HTTP API to scan uploaded data and files on demand.
+    Serve(ServeCommand),
+    /// Serve the clamd protocol on a Unix socket for existing clamd clients (Unix only).
+    Daemon(DaemonCommand),
+    /// Rules management commands.
+    Rules {
+        #[clap(subcommand)]
+        command: RulesCommand,
+    },
+    /// Quarantine management commands.
+    Quarantine {
+        #[clap(subcommand)]
+        command: QuarantineCommand,
+    },
+    /// Configuration file commands.
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct ScanCommand {
+    /// Files or folders to scan instead of --root, or - to scan the data read from the standard input.
+    #[clap(value_name = "PATH", conflicts_with_all = &["root", "processes"])]
+    pub paths: Vec<String>,
+    /// Name reported for the data read from the standard input.
+    #[clap(long, default_value = "stdin")]
+    pub label: String,
+    /// Scan the memory of the running processes instead of files (Linux only).
+    #[clap(long, takes_value = false)]
+    pub processes: bool,
+    #[clap(flatten)]
+    pub pool: WorkerArgs,
+    #[clap(flatten)]
+    pub rules: RulesArgs,
+    #[clap(flatten)]
+    pub files: FileArgs,
+    #[clap(flatten)]
+    pub process_filter: ProcessArgs,
+    #[clap(flatten)]
+    pub actions: ActionArgs,
+    #[clap(flatten)]
+    pub quarantine: QuarantineArgs,
+    #[clap(flatten)]
+    pub report: OutputArgs,
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct MonitorCommand {
+    #[clap(flatten)]
+    pub pool: WorkerArgs,
+    #[clap(flatten)]
+    pub rules: RulesArgs,
+    #[clap(flatten)]
+    pub files: FileArgs,
+    #[clap(flatten)]
+    pub monitor: MonitorArgs,
+    #[clap(flatten)]
+    pub actions: ActionArgs,
+    #[clap(flatten)]
+    pub quarantine: QuarantineArgs,
+    #[clap(flatten)]
+    pub report: OutputArgs,
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct ServeCommand {
+    #[clap(flatten)]
+    pub pool: WorkerArgs,
+    #[clap(flatten)]
+    pub rules: RulesArgs,
+    #[clap(flatten)]
+    pub serve: ServeArgs,
+    #[clap(flatten)]
+    pub report: OutputArgs,
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct DaemonCommand {
+    #[clap(flatten)]
+    pub pool: WorkerArgs,
+    #[clap(flatten)]
+    pub rules: RulesArgs,
+    #[clap(flatten)]
+    pub daemon: DaemonArgs,
+    #[clap(flatten)]
+    pub report: OutputArgs,
+}
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum RulesCommand {
+    /// Compile the rules and save them to a file that can be loaded with --compiled-rules.
+    Compile {
+        #[clap(flatten)]
+        rules: RulesArgs,
+        /// Where to save the compiled rules.
+        path: String,
+    },
+    /// List the loaded rules.
+    List {
+        #[clap(flatten)]
+        rules: RulesArgs,
+    },
+    /// Scan files or folders with the rules, without running any action, and exit.
+    Test {
+        #[clap(flatten)]
+        rules: RulesArgs,
+        #[clap(flatten)]
+        report: OutputArgs,
+        /// Files or folders to scan.
+        #[clap(required = true)]
+        paths: Vec<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum QuarantineCommand {
+    /// List the quarantined files.
+    List {
+        #[clap(flatten)]
+        quarantine: QuarantineArgs,
+    },
+    /// Restore a quarantined file to its original path.
+    Restore {
+        #[clap(flatten)]
+        quarantine: QuarantineArgs,
+        /// Id of the quarantined file, as printed by 'quarantine list'.
+        id: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum ConfigCommand {
+    /// Validate the configuration and print the effective settings.
+    Check,
+}
+
+/// The flat arguments used before commands were introduced, running the monitor by default.
+#[derive(Args, Debug)]
+pub(crate) struct LegacyArgs {
+    /// Perform a scan of every file in the specified root folder and exit, use 'scan' instead.
+    #[clap(long, takes_value = false)]
+    pub scan: bool,
+    /// Scan the memory of the running processes and exit, use 'scan --processes' instead.
+    #[clap(long, takes_value = false, conflicts_with = "scan")]
+    pub processes: bool,
+    /// Restore the quarantined file with this id to its original path and exit, use 'quarantine restore' instead.
+    #[clap(long)]
+    pub restore: Option<String>,
+    #[clap(flatten)]
+    pub pool: WorkerArgs,
+    #[clap(flatten)]
+    pub rules: RulesArgs,
+    #[clap(flatten)]
+    pub files: FileArgs,
+    #[clap(flatten)]
+    pub process_filter: ProcessArgs,
+    #[clap(flatten)]
+    pub monitor: MonitorArgs,
+    #[clap(flatten)]
+    pub actions: ActionArgs,
+    #[clap(flatten)]
+    pub quarantine: QuarantineArgs,
+    #[clap(flatten)]
+    pub report: OutputArgs,
+}
+
+#[derive(Args, Clone, Debug)]
+pub(crate) struct WorkerArgs {
+    /// Number of worker threads used for scanning.
+    #[clap(long, default_value_t = 32)]
+    pub workers: usize,
+}
+
+#[derive(Args, Clone, Debug)]
+pub(crate) struct RulesArgs {
+    /// Path of YARA rules to use.
+    #[clap(long)]
+    pub rules: Option<String>,
+    /// Extension of the rule files to load, can be passed multiple times (yar, yara, rule and yarc by default).
+    #[clap(long)]
+    pub rules_ext: Vec<String>,
+    /// How to assign YARA namespaces to the loaded rule files.
+    #[clap(long, value_enum, default_value = "folder")]
+    pub namespace: NamespaceArg,
+    /// Load precompiled rules from this file if they are up to date with the --rules sources.
+    #[clap(long)]
+    pub compiled_rules: Option<String>,
+    /// Save the compiled rules to this file, can be the same path used for --compiled-rules.
+    #[clap(long)]
+    pub save_compiled: Option<String>,
+    /// Abort if any rule file fails to compile instead of skipping it.
+    #[clap(long, takes_value = false)]
+    pub strict_rules: bool,
+    /// Define an external variable for the rules as NAME=VALUE, where VALUE is an integer, true, false or a string, can be passed multiple times.
+    #[clap(long, value_parser = parse_define)]
+    pub define: Vec<(String, MetaValue)>,
+    /// Scan timeout in seconds.
+    #[clap(long, default_value_t = 30)]
+    pub scan_timeout: i32,
+}
+
+#[derive(Args, Clone, Debug)]
+pub(crate) struct FileArgs {
+    /// Root path of the filesystem to scan or monitor, can be passed multiple times.
+    #[clap(long, default_value = "/")]
+    pub root: Vec<String>,
+    /// Only scan files with the specified extension, can be passed multiple times.
+    #[clap(long)]
+    pub ext: Vec<String>,
+    /// Do not scan files smaller than this size (in bytes, or with a K, M or G suffix).
+    #[clap(long, value_parser = parse_size, default_value = "0")]
+    pub min_file_size: u64,
+    /// Do not scan files bigger than this size (in bytes, or with a K, M or G suffix).
+    #[clap(long, value_parser = parse_size)]
+    pub max_file_size: Option<u64>,
+    /// Exclude paths matching this glob (or regular expression if prefixed with 're:') from scanning and monitoring, can be passed multiple times.
+    #[clap(long)]
+    pub exclude: Vec<String>,
+    /// Do not exclude /proc, /sys and /dev by default.
+    #[clap(long, takes_value = false)]
+    pub no_default_excludes: bool,
+    /// Scan the files inside zip, tar, gzip and bzip2 archives.
+    #[clap(long, takes_value = false)]
+    pub archives: bool,
+    /// Maximum nesting level of archives inside archives to extract.
+    #[clap(long, default_value_t = 3)]
+    pub archive_max_depth: usize,
+    /// Maximum uncompressed size extracted from each archive (in bytes, or with a K, M or G suffix).
+    #[clap(long, value_parser = parse_size, default_value = "256M")]
+    pub archive_max_size: u64,
+    /// Maximum number of files extracted from each archive.
+    #[clap(long, default_value_t = 10000)]
+    pub archive_max_members: usize,
+    /// Remember files found clean in this file and skip them until they or the rules change.
+    #[clap(long)]
+    pub cache: Option<String>,
+    /// Compute MD5, SHA-1 and SHA-256 of every scanned file, not only of detected ones, and report clean files with them.
+    #[clap(long, takes_value = false)]
+    pub hash_all: bool,
+}
+
+#[derive(Args, Clone, Debug)]
+pub(crate) struct ProcessArgs {
+    /// Only scan the process with this pid, can be passed multiple times.
+    #[clap(long)]
+    pub pid: Vec<u32>,
+    /// Only scan the processes of this user name or id, can be passed multiple times.
+    #[clap(long)]
+    pub user: Vec<String>,
+    /// Only scan the processes with this name, can be passed multiple times.
+    #[clap(long)]
+    pub process_name: Vec<String>,
+}
+
+#[derive(Args, Clone, Debug)]
+pub(crate) struct MonitorArgs {
+    /// Also scan the executable and memory of new processes (Linux only).
+    #[clap(long, takes_value = false)]
+    pub exec_monitor: bool,
+    /// How often to check for new processes, in milliseconds.
+    #[clap(long, default_value_t = 500)]
+    pub exec_poll_interval: u64,
+    /// Maximum number of files waiting to be scanned, events for further files are dropped.
+    #[clap(long, default_value_t = 10000)]
+    pub queue_size: usize,
+}
+
+#[derive(Args, Clone, Debug)]
+pub(crate) struct ActionArgs {
+    /// Action to run on detected files as '[rule:NAME|tag:NAME|severity:LEVEL=]ACTION' where ACTION is one of log, delete, quarantine or exec:COMMAND, can be passed multiple times.
+    #[clap(long, value_parser = parse_action)]
+    pub action: Vec<(Selector, Action)>,
+    /// Timeout in seconds for exec actions.
+    #[clap(long, default_value_t = 30)]
+    pub action_timeout: u64,
+    /// Move detected files to the quarantine folder, same as --action quarantine.
+    #[clap(long, takes_value = false)]
+    pub quarantine: bool,
+}
+
+#[derive(Args, Clone, Debug)]
+pub(crate) struct QuarantineArgs {
+    /// Quarantine folder.
+    #[clap(long, default_value = DEFAULT_QUARANTINE_DIR)]
+    pub quarantine_dir: String,
+}
+
+#[derive(Args, Clone, Debug)]
+pub(crate) struct OutputArgs {
+    /// Output format for detections, errors and scan summaries.
+    #[clap(long, value_enum, default_value = "text")]
+    pub output: OutputArg,
+    /// Write json output to this file instead of the standard output.
+    #[clap(long)]
+    pub output_file: Option<String>,
+}
+
+#[derive(Args, Clone, Debug)]
+pub(crate) struct ServeArgs {
+    /// Address to serve the API on, or Unix socket path prefixed with 'unix:'.
+    #[clap(long, default_value = "127.0.0.1:8080")]
+    pub listen: String,
+    /// Maximum size of the request bodies (in bytes, or with a K, M or G suffix).
+    #[clap(long, value_parser = parse_size, default_value = "64M")]
+    pub max_body_size: u64,
+    /// Enable POST /scan/path, letting any client of the API scan the files the server can read.
+    #[clap(long, takes_value = false)]
+    pub allow_path_scan: bool,
+    /// Allow listening on an address that is not loopback, the API has no authentication.
+    #[clap(long, takes_value = false)]
+    pub allow_remote: bool,
+}
+
+#[derive(Args, Clone, Debug)]
+pub(crate) struct DaemonArgs {
+    /// Unix socket to serve the clamd protocol on.
+    #[clap(long, default_value = "/run/sauron/clamd.sock")]
+    pub socket: String,
+    /// Maximum size of the data scanned with INSTREAM (in bytes, or with a K, M or G suffix).
+    #[clap(long, value_parser = parse_size, default_value = "25M")]
+    pub max_stream_size: u64,
+}
+
+/// The values of --namespace, mapped to the modes of the engine.
+#[derive(ValueEnum, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum NamespaceArg {
+    /// Load every rule file in the default namespace.
+    Default,
+    /// Load each rule file in its own namespace.
+    File,
+    /// Load each top-level subfolder of the rules path in its own namespace.
+    Folder,
+}
+
+impl From<&NamespaceArg> for NamespaceMode {
+    fn from(arg: &NamespaceArg) -> Self {
+        match arg {
+            NamespaceArg::Default => NamespaceMode::Default,
+            NamespaceArg::File => NamespaceMode::File,
+            NamespaceArg::Folder => NamespaceMode::Folder,
+        }
+    }
+}
+
+/// The values of --output, mapped to the formats of the reporters.
+#[derive(ValueEnum, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum OutputArg {
+    /// Human readable log lines.
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+impl From<&OutputArg> for OutputFormat {
+    fn from(arg: &OutputArg) -> Self {
+        match arg {
+            OutputArg::Text => OutputFormat::Text,
+            OutputArg::Json => OutputFormat::Json,
+        }
+    }
+}
+
+// the default settings of a group are the defaults of its command line arguments
+macro_rules! defaults_from_args {
+    ($($group:ty),*) => {
+        $(
+            impl Default for $group {
+                fn default() -> Self {
+                    let command = <$group>::augment_args(clap::Command::new("sauron"));
+                    command
+                        .try_get_matches_from(["sauron"])
+                        .and_then(|matches| <$group>::from_arg_matches(&matches))
+                        .expect("invalid default arguments")
+                }
+            }
+        )*
+    };
+}
+
+defaults_from_args!(
+    WorkerArgs,
+    RulesArgs,
+    FileArgs,
+    ProcessArgs,
+    MonitorArgs,
+    ActionArgs,
+    QuarantineArgs,
+    ServeArgs,
+    DaemonArgs,
+    OutputArgs
+);
+
+/// The effective settings, from the command line and the configuration file.
+/// Settings not supported by the command being run are left to their defaults.
+#[derive(Debug, Default)]
+pub(crate) struct Arguments {
+    pub pool: WorkerArgs,
+    pub rules: RulesArgs,
+    pub files: FileArgs,
+    pub processes: ProcessArgs,
+    pub monitor: MonitorArgs,
+    pub actions: ActionArgs,
+    pub quarantine: QuarantineArgs,
+    pub serve: ServeArgs,
+    pub daemon: DaemonArgs,
+    pub report: OutputArgs,
+}
+
+impl Arguments {
+    /// The settings of the scan and monitor APIs.
+    pub fn options(&self) -> Options {
+        let mut actions = self.actions.action.clone();
+        // --quarantine is a shortcut for --action quarantine
+        if self.actions.quarantine {
+            actions.push((Selector::Any, Action::Quarantine));
+        }
+
+        Options {
+            workers: self.pool.workers,
+            extensions: self.files.ext.clone(),
+            exclude: self.files.exclude.clone(),
+            default_excludes: !self.files.no_default_excludes,
+            // never scan our own output
+            skip_paths: self.report.output_file.iter().cloned().collect(),
+            archives: self.files.archives.then_some(Limits {
+                max_depth: self.files.archive_max_depth,
+                max_size: self.files.archive_max_size,
+                max_members: self.files.archive_max_members,
+            }),
+            cache: self.files.cache.clone(),
+            hash_all: self.files.hash_all,
+            actions,
+            action_timeout: Duration::from_secs(self.actions.action_timeout),
+            quarantine_dir: self.quarantine.quarantine_dir.clone(),
+            pids: self.processes.pid.clone(),
+            users: self.processes.user.clone(),
+            process_names: self.processes.process_name.clone(),
+            queue_size: self.monitor.queue_size,
+            exec_monitor: self
+                .monitor
+                .exec_monitor
+                .then_some(Duration::from_millis(self.monitor.exec_poll_interval)),
+        }
+    }
+}
+
+impl Cli {
+    /// The settings passed on the command line.
+    pub fn arguments(&self) -> Arguments {
+        let mut args = Arguments::default();
+
+        match &self.command {
+            None => {
+                let legacy = &self.legacy;
+                args.pool = legacy.pool.clone();
+                args.rules = legacy.rules.clone();
+                args.files = legacy.files.clone();
+                args.processes = legacy.process_filter.clone();
+                args.monitor = legacy.monitor.clone();
+                args.actions = legacy.actions.clone();
+                args.quarantine = legacy.quarantine.clone();
+                args.report = legacy.report.clone();
+            }
+            Some(Command::Scan(scan)) => {
+                args.pool = scan.pool.clone();
+                args.rules = scan.rules.clone();
+                args.files = scan.files.clone();
+                args.processes = scan.process_filter.clone();
+                args.actions = scan.actions.clone();
+                args.quarantine = scan.quarantine.clone();
+                args.report = scan.report.clone();
+            }
+            Some(Command::Monitor(monitor)) => {
+                args.pool = monitor.pool.clone();
+                args.rules = monitor.rules.clone();
+                args.files = monitor.files.clone();
+                args.monitor = monitor.monitor.clone();
+                args.actions = monitor.actions.clone();
+                args.quarantine = monitor.quarantine.clone();
+                args.report = monitor.report.clone();
+            }
+            Some(Command::Serve(serve)) => {
+                args.pool = serve.pool.clone();
+                args.rules = serve.rules.clone();
+                args.serve = serve.serve.clone();
+                args.report = serve.report.clone();
+            }
+            Some(Command::Daemon(daemon)) => {
+                args.pool = daemon.pool.clone();
+                args.rules = daemon.rules.clone();
+                args.daemon = daemon.daemon.clone();
+                args.report = daemon.report.clone();
+            }
+            Some(Command::Rules { command }) => match command {
+                RulesCommand::Compile { rules, .. } | RulesCommand::List { rules } => {
+                    args.rules = rules.clone();
+                }
+                RulesCommand::Test { rules, report, .. } => {
+                    args.rules = rules.clone();
+                    args.report = report.clone();
+                }
+            },
+            Some(Command::Quarantine { command }) => match command {
+                QuarantineCommand::List { quarantine }
+                | QuarantineCommand::Restore { quarantine, .. } => {
+                    args.quarantine = quarantine.clone();
+                }
+            },
+            Some(Command::Config { .. }) => {}
+        }
+
+        args
+    }
+}
+
+/// Parses a size in bytes with an optional K, M or G suffix.
+pub(crate) fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (number, multiplier) = match value.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&value[..value.len() - 1], 1024),
+        Some('M') => (&value[..value.len() - 1], 1024 * 1024),
+        Some('G') => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+
+    number
+        .trim()
+        .parse::<u64>()
+        .map_err(|e| format!("invalid size '{}': {}", value, e))?
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("size '{}' is too big", value))
+}
+
+// parse a NAME=VALUE external variable definition
+fn parse_define(value: &str) -> Result<(String, MetaValue), String> {
+    let (name, value) = value
+        .split_once('=')
+        .ok_or_else(|| format!("invalid definition '{}', expected NAME=VALUE", value))?;
+
+    if BUILTIN_EXTERNALS.contains(&name) {
+        return Err(format!("'{}' is set for each scanned file", name));
+    }
+
+    let value = if let Ok(i) = value.parse::<i64>() {
+        MetaValue::Integer(i)
+    } else if let Ok(b) = value.parse::<bool>() {
+        MetaValue::Boolean(b)
+    } else {
+        MetaValue::String(value.to_string())
+    };
+
+    Ok((name.to_string(), value))
+}
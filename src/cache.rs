@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::hashes::Hashes;
+
+// save the cache after this much time if anything changed, it's also saved once done scanning
+const SAVE_EVERY: Duration = Duration::from_secs(60);
+
+// identifies a version of a file without reading it
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+struct Key {
+    dev: u64,
+    ino: u64,
+    size: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+}
+
+impl Key {
+    #[cfg(unix)]
+    fn of(path: &Path) -> Option<Self> {
+        use std::os::unix::fs::MetadataExt;
+
+        let metadata = fs::metadata(path).ok()?;
+        Some(Key {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            size: metadata.size(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+        })
+    }
+
+    // without inodes only the content hash can be used
+    #[cfg(not(unix))]
+    fn of(_path: &Path) -> Option<Self> {
+        None
+    }
+
+    // identifies the file regardless of its version
+    fn inode(&self) -> (u64, u64) {
+        (self.dev, self.ino)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Entry {
+    #[serde(flatten)]
+    key: Key,
+    sha256: Option<String>,
+}
+
+// on disk representation of the cache
+#[derive(Serialize, Deserialize)]
+struct Data {
+    fingerprint: String,
+    entries: Vec<Entry>,
+}
+
+struct State {
+    // fingerprint of the rules and settings the clean results were obtained with
+    fingerprint: String,
+    // only the last version of each file found clean is kept
+    by_inode: HashMap<(u64, u64), Entry>,
+    // number of entries with each content hash, the hashes of files without an inode have no
+    // entry to release them and are kept until the rules change
+    by_hash: HashMap<String, usize>,
+    unsaved: usize,
+    saved_at: Instant,
+}
+
+impl State {
+    // drop all results if the rules or settings changed
+    fn sync(&mut self, fingerprint: &str) {
+        if self.fingerprint != fingerprint {
+            if !self.by_inode.is_empty() || !self.by_hash.is_empty() {
+                log::info!("rules or settings changed, invalidating scan cache");
+            }
+            self.fingerprint = fingerprint.to_string();
+            self.by_inode.clear();
+            self.by_hash.clear();
+            self.unsaved += 1;
+        }
+    }
+
+    // record the clean version of a file, releasing the hash of the version it replaces
+    fn insert(&mut self, key: Option<Key>, sha256: Option<String>) {
+        if let Some(sha256) = &sha256 {
+            *self.by_hash.entry(sha256.clone()).or_default() += 1;
+        }
+
+        let replaced = key.and_then(|key| self.by_inode.insert(key.inode(), Entry { key, sha256 }));
+        if let Some(sha256) = replaced.and_then(|entry| entry.sha256) {
+            if let Some(count) = self.by_hash.get_mut(&sha256) {
+                *count -= 1;
+                if *count == 0 {
+                    self.by_hash.remove(&sha256);
+                }
+            }
+        }
+    }
+}
+
+/// A file that has to be scanned, identified as it was before scanning.
+pub(crate) struct Pending {
+    key: Option<Key>,
+    hashes: Option<Hashes>,
+}
+
+impl Pending {
+    /// Hashes of the file computed by the lookup, if it could be read.
+    pub fn hashes(&self) -> Option<&Hashes> {
+        self.hashes.as_ref()
+    }
+}
+
+/// Result of a cache lookup.
+pub(crate) enum Lookup {
+    /// The file is known to be clean for the current rules.
+    Clean,
+    /// The file has to be scanned.
+    Miss(Pending),
+}
+
+/// Persistent cache of the files found clean by a given set of rules.
+pub(crate) struct ScanCache {
+    path: PathBuf,
+    state: Mutex<State>,
+}
+
+impl ScanCache {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let path = PathBuf::from(path);
+        let mut state = State {
+            fingerprint: String::new(),
+            by_inode: HashMap::new(),
+            by_hash: HashMap::new(),
+            unsaved: 0,
+            saved_at: Instant::now(),
+        };
+
+        if path.exists() {
+            let data = fs::read_to_string(&path)
+                .map_err(|e| format!("can't read scan cache {:?}: {:?}", &path, e))?;
+            match serde_json::from_str::<Data>(&data) {
+                Ok(data) => {
+                    state.fingerprint = data.fingerprint;
+                    for entry in data.entries {
+                        state.insert(Some(entry.key), entry.sha256);
+                    }
+                    log::info!("{} entries loaded from scan cache", state.by_inode.len());
+                }
+                Err(e) => log::warn!("ignoring invalid scan cache {:?}: {:?}", &path, e),
+            }
+        }
+
+        Ok(ScanCache {
+            path,
+            state: Mutex::new(state),
+        })
+    }
+
+    /// Checks if the file is known to be clean for the rules and settings with this fingerprint.
+    pub fn lookup(&self, path: &Path, fingerprint: &str) -> Lookup {
+        let key = Key::of(path);
+
+        {
+            let mut state = self.state.lock().unwrap();
+            state.sync(fingerprint);
+            if let Some(key) = &key {
+                if state
+                    .by_inode
+                    .get(&key.inode())
+                    .is_some_and(|entry| entry.key == *key)
+                {
+                    return Lookup::Clean;
+                }
+            }
+        }
+
+        // same file moved, copied or touched, check its content hash (without holding the lock),
+        // the other hashes are computed in the same pass to be reused if the file is detected
+        let hashes = match Hashes::of_file(path) {
+            Ok(hashes) => hashes,
+            Err(_) => return Lookup::Miss(Pending { key, hashes: None }),
+        };
+
+        let mut state = self.state.lock().unwrap();
+        if state.fingerprint == fingerprint && state.by_hash.contains_key(&hashes.sha256) {
+            if key.is_some() {
+                state.insert(key, Some(hashes.sha256));
+                state.unsaved += 1;
+            }
+            Lookup::Clean
+        } else {
+            Lookup::Miss(Pending {
+                key,
+                hashes: Some(hashes),
+            })
+        }
+    }
+
+    /// Records that the file is clean for the rules and settings with this fingerprint.
+    pub fn insert_clean(&self, pending: Pending, fingerprint: &str) {
+        let Pending { key, hashes } = pending;
+        let sha256 = hashes.map(|hashes| hashes.sha256);
+        let mut state = self.state.lock().unwrap();
+
+        // the rules changed while this file was being scanned
+        if state.fingerprint != fingerprint {
+            return;
+        }
+
+        state.insert(key, sha256);
+        state.unsaved += 1;
+
+        if state.saved_at.elapsed() >= SAVE_EVERY {
+            let data = Self::snapshot(&mut state);
+            // don't block the other workers while writing
+            drop(state);
+            self.write(&data);
+        }
+    }
+
+    /// Writes the cache to disk if it changed.
+    pub fn save(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.unsaved > 0 {
+            let data = Self::snapshot(&mut state);
+            drop(state);
+            self.write(&data);
+        }
+    }
+
+    // copy the entries to save, marking them as saved
+    fn snapshot(state: &mut State) -> Data {
+        state.unsaved = 0;
+        state.saved_at = Instant::now();
+
+        Data {
+            fingerprint: state.fingerprint.clone(),
+            entries: state.by_inode.values().cloned().collect(),
+        }
+    }
+
+    fn write(&self, data: &Data) {
+        // write to a temporary file first so that the cache is never partially written
+        let tmp_path = self.path.with_extension("tmp");
+        let res = serde_json::to_string(data)
+            .map_err(|e| e.to_string())
+            .and_then(|json| {
+                fs::write(&tmp_path, json)
+                    .and_then(|_| fs::rename(&tmp_path, &self.path))
+                    .map_err(|e| e.to_string())
+            });
+
+        match res {
+            Ok(_) => log::debug!("{} entries saved to scan cache", data.entries.len()),
+            Err(e) => log::error!("can't save scan cache to {:?}: {}", &self.path, e),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::engine::test_path;
+
+    #[test]
+    fn forgets_the_hash_of_replaced_versions() {
+        let cache_path = test_path("cache.json");
+        let cache = ScanCache::load(cache_path.to_str().unwrap()).unwrap();
+        let one = test_path("one");
+        let two = test_path("two");
+        let clean = |path: &Path| match cache.lookup(path, "rules") {
+            Lookup::Clean => true,
+            Lookup::Miss(pending) => {
+                cache.insert_clean(pending, "rules");
+                false
+            }
+        };
+
+        // two files with the same content share its hash
+        fs::write(&one, "old").unwrap();
+        fs::write(&two, "old").unwrap();
+        assert!(!clean(&one));
+        assert!(clean(&two));
+        assert_eq!(cache.state.lock().unwrap().by_hash.len(), 1);
+
+        // still used by the second file
+        fs::write(&one, "new data").unwrap();
+        assert!(!clean(&one));
+        assert_eq!(cache.state.lock().unwrap().by_hash.len(), 2);
+
+        // not used anymore
+        fs::write(&two, "newer data").unwrap();
+        assert!(!clean(&two));
+        {
+            let state = cache.state.lock().unwrap();
+            assert_eq!(state.by_hash.len(), 2);
+            assert!(!state.by_hash.contains_key(&Hashes::of_bytes(b"old").sha256));
+        }
+
+        for path in [cache_path, one, two] {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
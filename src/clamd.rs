@@ -0,0 +1,310 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use walkdir::WalkDir;
+
+use crate::engine::{Detection, Engine, Error};
+use crate::report::Reporter;
+
+// label of the data scanned with INSTREAM, as reported by clamd
+const STREAM_NAME: &str = "stream";
+
+// longest command line accepted, paths included
+const MAX_COMMAND_SIZE: u64 = 4096;
+
+// how long a client can stay idle, like clamd's ReadTimeout, so that it can't hold a worker forever
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How a command and its reply are delimited: 'z' prefixed commands use NUL, 'n' prefixed and
+/// unprefixed ones use newlines.
+#[derive(Clone, Copy)]
+enum Delimiter {
+    Nul,
+    Newline,
+}
+
+impl Delimiter {
+    fn byte(&self) -> u8 {
+        match self {
+            Delimiter::Nul => b'\0',
+            Delimiter::Newline => b'\n',
+        }
+    }
+}
+
+/// Serves a subset of the clamd protocol backed by the engine.
+struct Daemon {
+    engine: Arc<Engine>,
+    reporter: Arc<dyn Reporter>,
+    max_stream_size: u64,
+}
+
+impl Daemon {
+    fn handle(&self, stream: UnixStream) -> Result<(), String> {
+        stream
+            .set_read_timeout(Some(CONNECTION_TIMEOUT))
+            .and_then(|_| stream.set_write_timeout(Some(CONNECTION_TIMEOUT)))
+            .map_err(|e| format!("can't set timeouts: {:?}", e))?;
+
+        let mut reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+        let mut writer = stream;
+
+        // the prefix tells the delimiter of the command
+        let mut prefix = [0u8];
+        reader
+            .read_exact(&mut prefix)
+            .map_err(|e| format!("can't read command: {:?}", e))?;
+
+        let (delimiter, mut line) = match prefix[0] {
+            b'z' => (Delimiter::Nul, vec![]),
+            b'n' => (Delimiter::Newline, vec![]),
+            byte => (Delimiter::Newline, vec![byte]),
+        };
+
+        reader
+            .by_ref()
+            .take(MAX_COMMAND_SIZE)
+            .read_until(delimiter.byte(), &mut line)
+            .map_err(|e| format!("can't read command: {:?}", e))?;
+        if line.last() == Some(&delimiter.byte()) {
+            line.pop();
+        }
+
+        let line = String::from_utf8_lossy(&line);
+        let (command, argument) = line.split_once(' ').unwrap_or((line.as_ref(), ""));
+
+        log::debug!("clamd command {} {:?}", command, argument);
+
+        let replies = match command {
+            "PING" => vec!["PONG".to_string()],
+            "VERSION" => vec![format!(
+                "sauron {}/{}",
+                env!("CARGO_PKG_VERSION"),
+                self.engine.loaded_rules().len()
+            )],
+            "SCAN" | "CONTSCAN" | "MULTISCAN" => self.scan_path(Path::new(argument)),
+            "INSTREAM" => vec![self.scan_stream(&mut reader)],
+            _ => vec!["UNKNOWN COMMAND".to_string()],
+        };
+
+        for reply in replies {
+            writer
+                .write_all(reply.as_bytes())
+                .and_then(|_| writer.write_all(&[delimiter.byte()]))
+                .map_err(|e| format!("can't send reply: {:?}", e))?;
+        }
+
+        Ok(())
+    }
+
+    // clamd reports a single signature for each detected file
+    fn reply(&self, name: &str, res: &Detection) -> Option<String> {
+        if let Some(error) = &res.error {
+            self.reporter.error(&res.path, error);
+            Some(format!("{}: {} ERROR", name, error))
+        } else if res.detected {
+            self.reporter.detection(res);
+            let signature = res
+                .all_matches()
+                .first()
+                .map(|rule| rule.identifier.clone())
+                .unwrap_or_default();
+            Some(format!("{}: {} FOUND", name, signature))
+        } else {
+            None
+        }
+    }
+
+    // every file is scanned, one reply for each detection or error, or OK if there's none
+    fn scan_path(&self, path: &Path) -> Vec<String> {
+        if !path.exists() {
+            return vec![format!(
+                "{}: No such file or directory. ERROR",
+                path.display()
+            )];
+        }
+
+        let replies: Vec<String> = WalkDir::new(path)
+            .follow_links(true)
+            .into_iter()
+            .filter_map(|entry| match entry {
+                Ok(entry) if entry.file_type().is_file() => {
+                    let path = entry.path().to_path_buf();
+                    self.reply(&path.display().to_string(), &self.engine.scan(&path))
+                }
+                Ok(_) => None,
+                Err(e) => Some(format!(
+                    "{}: {} ERROR",
+                    e.path().unwrap_or(path).display(),
+                    e
+                )),
+            })
+            .collect();
+
+        if replies.is_empty() {
+            vec![format!("{}: OK", path.display())]
+        } else {
+            replies
+        }
+    }
+
+    // the data is sent as chunks prefixed by their big endian 32 bits length, until an empty one
+    fn scan_stream<R: Read>(&self, reader: &mut R) -> String {
+        let mut data = vec![];
+
+        loop {
+            let mut length = [0u8; 4];
+            if let Err(e) = reader.read_exact(&mut length) {
+                return format!("{}: can't read chunk: {:?} ERROR", STREAM_NAME, e);
+            }
+
+            let length = u32::from_be_bytes(length) as u64;
+            if length == 0 {
+                break;
+            }
+            if data.len() as u64 + length > self.max_stream_size {
+                return "INSTREAM size limit exceeded. ERROR".to_string();
+            }
+
+            if let Err(e) = reader.take(length).read_to_end(&mut data) {
+                return format!("{}: can't read chunk: {:?} ERROR", STREAM_NAME, e);
+            }
+        }
+
+        let res = self.engine.scan_bytes(&data, STREAM_NAME);
+        self.reply(STREAM_NAME, &res)
+            .unwrap_or_else(|| format!("{}: OK", STREAM_NAME))
+    }
+}
+
+/// Serves the PING, VERSION, SCAN, CONTSCAN, MULTISCAN and INSTREAM commands of the clamd
+/// protocol on a Unix socket, handling up to as many connections at a time as the number of
+/// workers. Each connection is closed after replying to its command.
+pub fn serve_clamd(
+    engine: Arc<Engine>,
+    socket: &str,
+    workers: usize,
+    max_stream_size: u64,
+    reporter: Arc<dyn Reporter>,
+) -> Result<(), Error> {
+    // remove the socket left by a previous run
+    if fs::metadata(socket).is_ok_and(|meta| meta.file_type().is_socket()) {
+        fs::remove_file(socket).map_err(|e| format!("can't remove {}: {:?}", socket, e))?;
+    }
+
+    let listener = Arc::new(
+        UnixListener::bind(socket).map_err(|e| format!("can't listen on {}: {:?}", socket, e))?,
+    );
+    let daemon = Arc::new(Daemon {
+        engine,
+        reporter,
+        max_stream_size,
+    });
+
+    log::info!(
+        "serving clamd protocol on {} with {} workers ...",
+        socket,
+        workers
+    );
+
+    let handles: Vec<_> = (0..workers.max(1))
+        .map(|_| {
+            let listener = listener.clone();
+            let daemon = daemon.clone();
+
+            thread::spawn(move || loop {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        if let Err(e) = daemon.handle(stream) {
+                            log::debug!("clamd connection error: {}", e);
+                        }
+                    }
+                    Err(e) => log::error!("can't accept connection: {:?}", e),
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        let _ = handle.join();
+    }
+
+    Err(format!("stopped serving on {}", socket))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    use crate::engine::test_engine;
+    use crate::report::TextReporter;
+
+    fn daemon(max_stream_size: u64) -> Daemon {
+        Daemon {
+            engine: Arc::new(test_engine(
+                r#"rule evil { strings: $a = "EVILSTRING" condition: $a }"#,
+            )),
+            reporter: Arc::new(TextReporter {}),
+            max_stream_size,
+        }
+    }
+
+    // frame the chunks as an INSTREAM client would, ending with an empty one
+    fn stream(chunks: &[&[u8]]) -> Cursor<Vec<u8>> {
+        let mut data = vec![];
+        for chunk in chunks {
+            data.extend_from_slice(&(chunk.len() as u32).to_be_bytes());
+            data.extend_from_slice(chunk);
+        }
+        data.extend_from_slice(&[0; 4]);
+        Cursor::new(data)
+    }
+
+    #[test]
+    fn scans_clean_stream() {
+        let reply = daemon(1024).scan_stream(&mut stream(&[b"hello", b" world"]));
+        assert_eq!(reply, "stream: OK");
+    }
+
+    #[test]
+    fn joins_chunks() {
+        // the match spans the two chunks
+        let reply = daemon(1024).scan_stream(&mut stream(&[b"xxEVIL", b"STRINGxx"]));
+        assert_eq!(reply, "stream: evil FOUND");
+    }
+
+    #[test]
+    fn scans_empty_stream() {
+        let reply = daemon(1024).scan_stream(&mut stream(&[]));
+        assert_eq!(reply, "stream: OK");
+    }
+
+    #[test]
+    fn limits_stream_size() {
+        let daemon = daemon(10);
+
+        let reply = daemon.scan_stream(&mut stream(&[b"EVILSTRING"]));
+        assert_eq!(reply, "stream: evil FOUND");
+
+        let reply = daemon.scan_stream(&mut stream(&[b"EVILST", b"RING!"]));
+        assert_eq!(reply, "INSTREAM size limit exceeded. ERROR");
+    }
+
+    #[test]
+    fn fails_on_truncated_stream() {
+        let mut data = 100u32.to_be_bytes().to_vec();
+        data.extend_from_slice(b"short");
+        let reply = daemon(1024).scan_stream(&mut Cursor::new(data));
+        assert!(reply.starts_with("stream: can't read chunk"), "{}", reply);
+
+        let reply = daemon(1024).scan_stream(&mut Cursor::new(vec![0, 0]));
+        assert!(reply.starts_with("stream: can't read chunk"), "{}", reply);
+    }
+}
@@ -0,0 +1,124 @@
+use std::io;
+use std::sync::Arc;
+use std::time::Instant;
+
+use walkdir::WalkDir;
+
+use sauron::quarantine::Quarantine;
+use sauron::{Engine, Reporter, Scan, Summary};
+
+use crate::args::Arguments;
+
+/// Waits for a scan to complete and reports its summary, the results being reported as they come.
+pub(crate) fn wait(mut scan: Scan, reporter: &dyn Reporter) -> Summary {
+    scan.by_ref().for_each(drop);
+
+    let summary = scan.summary();
+    reporter.summary(&summary);
+
+    summary
+}
+
+/// Scans the data read from the standard input, reported with this label.
+pub(crate) fn scan_stdin(
+    engine: &Engine,
+    args: &Arguments,
+    label: &str,
+    reporter: Arc<dyn Reporter>,
+) -> Result<Summary, String> {
+    if !args.options().actions.is_empty() {
+        log::warn!("actions are not run on the standard input");
+    }
+
+    let res = engine.scan_reader(io::stdin().lock(), label);
+
+    // data that could not be read or is outside of the size limits has not been scanned
+    let mut summary = Summary {
+        scanned: res.error.is_none() as u32,
+        elapsed: res.elapsed,
+        ..Summary::default()
+    };
+    if let Some(error) = &res.error {
+        reporter.error(&res.path, error);
+        summary.errors += 1;
+    } else if res.detected {
+        reporter.detection(&res);
+        summary.detected += 1;
+    }
+    reporter.summary(&summary);
+
+    Ok(summary)
+}
+
+/// Prints the loaded rules.
+pub(crate) fn rules_list(engine: &Engine) -> Result<(), String> {
+    for rule in engine.loaded_rules() {
+        println!("{}", rule);
+    }
+    Ok(())
+}
+
+/// Scans files and folders with the rules and reports the results, without running any action.
+pub(crate) fn rules_test(
+    engine: &Engine,
+    paths: &[String],
+    reporter: Arc<dyn Reporter>,
+) -> Result<Summary, String> {
+    let start = Instant::now();
+    let mut summary = Summary::default();
+
+    for path in paths {
+        for entry in WalkDir::new(path).follow_links(true) {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    reporter.error(
+                        e.path().unwrap_or_else(|| path.as_ref()),
+                        &format!("{:?}", e),
+                    );
+                    summary.errors += 1;
+                    continue;
+                }
+            };
+            if !entry.file_type().is_file() {
+                continue;
+            }
+
+            let res = engine.scan(&entry.path().to_path_buf());
+            if let Some(error) = &res.error {
+                reporter.error(&res.path, error);
+                summary.errors += 1;
+            } else if res.detected {
+                reporter.detection(&res);
+                summary.detected += 1;
+            }
+            summary.scanned += 1;
+        }
+    }
+
+    summary.elapsed = start.elapsed();
+    reporter.summary(&summary);
+
+    Ok(summary)
+}
+
+/// Prints the quarantined files.
+pub(crate) fn quarantine_list(args: &Arguments) -> Result<(), String> {
+    for record in Quarantine::new(&args.quarantine.quarantine_dir)?.list()? {
+        println!(
+            "{}  {}  {:?}  {}",
+            &record.id,
+            &record.quarantined_at,
+            &record.original_path,
+            record.rules.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Moves a quarantined file back to its original path.
+pub(crate) fn quarantine_restore(args: &Arguments, id: &str) -> Result<(), String> {
+    let record = Quarantine::new(&args.quarantine.quarantine_dir)?.restore(id)?;
+    log::info!("{} restored to {:?}", id, &record.original_path);
+    Ok(())
+}
@@ -0,0 +1,433 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use clap::parser::ValueSource;
+use clap::ArgMatches;
+use serde::{Deserialize, Serialize};
+
+use sauron::{parse_action, MetaValue, Selector, BUILTIN_EXTERNALS};
+
+use crate::args::{parse_size, Arguments, NamespaceArg, OutputArg};
+
+/// Configuration file loaded when --config is not passed, if it exists.
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/sauron/sauron.toml";
+
+/// A size in bytes, or a string with a K, M or G suffix.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum Size {
+    Bytes(u64),
+    Text(String),
+}
+
+impl Size {
+    fn bytes(&self) -> Result<u64, String> {
+        match self {
+            Size::Bytes(bytes) => Ok(*bytes),
+            Size::Text(text) => parse_size(text),
+        }
+    }
+}
+
+/// Settings of the configuration file, named after the command line arguments.
+/// The arguments selecting what to run, like --processes, are not included.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct FileConfig {
+    root: Option<Vec<String>>,
+    rules: Option<String>,
+    rules_ext: Option<Vec<String>>,
+    namespace: Option<NamespaceArg>,
+    compiled_rules: Option<String>,
+    save_compiled: Option<String>,
+    strict_rules: Option<bool>,
+    workers: Option<usize>,
+    scan_timeout: Option<i32>,
+    pid: Option<Vec<u32>>,
+    user: Option<Vec<String>>,
+    process_name: Option<Vec<String>>,
+    exec_monitor: Option<bool>,
+    exec_poll_interval: Option<u64>,
+    queue_size: Option<usize>,
+    ext: Option<Vec<String>>,
+    min_file_size: Option<Size>,
+    max_file_size: Option<Size>,
+    exclude: Option<Vec<String>>,
+    no_default_excludes: Option<bool>,
+    action: Option<Vec<String>>,
+    action_timeout: Option<u64>,
+    quarantine: Option<bool>,
+    quarantine_dir: Option<String>,
+    archives: Option<bool>,
+    archive_max_depth: Option<usize>,
+    archive_max_size: Option<Size>,
+    archive_max_members: Option<usize>,
+    cache: Option<String>,
+    hash_all: Option<bool>,
+    output: Option<OutputArg>,
+    output_file: Option<String>,
+    listen: Option<String>,
+    max_body_size: Option<Size>,
+    allow_path_scan: Option<bool>,
+    allow_remote: Option<bool>,
+    socket: Option<String>,
+    max_stream_size: Option<Size>,
+    // tables have to come after plain values in TOML
+    define: Option<BTreeMap<String, MetaValue>>,
+}
+
+impl FileConfig {
+    fn load(path: &str) -> Result<Self, String> {
+        let data = fs::read_to_string(path).map_err(|e| format!("can't read {}: {:?}", path, e))?;
+
+        toml::from_str(&data).map_err(|e| format!("can't parse {}: {}", path, e))
+    }
+
+    /// The effective settings of these arguments.
+    pub fn from_args(args: &Arguments) -> Self {
+        let Arguments {
+            pool,
+            rules,
+            files,
+            processes,
+            monitor,
+            actions,
+            quarantine,
+            serve,
+            daemon,
+            report,
+        } = args;
+
+        FileConfig {
+            root: Some(files.root.clone()),
+            rules: rules.rules.clone(),
+            rules_ext: Some(rules.rules_ext.clone()),
+            namespace: Some(rules.namespace.clone()),
+            compiled_rules: rules.compiled_rules.clone(),
+            save_compiled: rules.save_compiled.clone(),
+            strict_rules: Some(rules.strict_rules),
+            workers: Some(pool.workers),
+            scan_timeout: Some(rules.scan_timeout),
+            pid: Some(processes.pid.clone()),
+            user: Some(processes.user.clone()),
+            process_name: Some(processes.process_name.clone()),
+            exec_monitor: Some(monitor.exec_monitor),
+            exec_poll_interval: Some(monitor.exec_poll_interval),
+            queue_size: Some(monitor.queue_size),
+            ext: Some(files.ext.clone()),
+            min_file_size: Some(Size::Bytes(files.min_file_size)),
+            max_file_size: files.max_file_size.map(Size::Bytes),
+            exclude: Some(files.exclude.clone()),
+            no_default_excludes: Some(files.no_default_excludes),
+            action: Some(
+                actions
+                    .action
+                    .iter()
+                    .map(|(selector, action)| match selector {
+                        Selector::Any => action.to_string(),
+                        selector => format!("{}={}", selector, action),
+                    })
+                    .collect(),
+            ),
+            action_timeout: Some(actions.action_timeout),
+            quarantine: Some(actions.quarantine),
+            quarantine_dir: Some(quarantine.quarantine_dir.clone()),
+            archives: Some(files.archives),
+            archive_max_depth: Some(files.archive_max_depth),
+            archive_max_size: Some(Size::Bytes(files.archive_max_size)),
+            archive_max_members: Some(files.archive_max_members),
+            cache: files.cache.clone(),
+            hash_all: Some(files.hash_all),
+            output: Some(report.output.clone()),
+            output_file: report.output_file.clone(),
+            listen: Some(serve.listen.clone()),
+            max_body_size: Some(Size::Bytes(serve.max_body_size)),
+            allow_path_scan: Some(serve.allow_path_scan),
+            allow_remote: Some(serve.allow_remote),
+            socket: Some(daemon.socket.clone()),
+            max_stream_size: Some(Size::Bytes(daemon.max_stream_size)),
+            define: Some(rules.define.iter().cloned().collect()),
+        }
+    }
+
+    // set the arguments that have not been passed on the command line to the file values
+    fn apply(self, args: &mut Arguments, matches: &ArgMatches) -> Result<(), String> {
+        // the argument ids are the kebab-case field names
+        let from_file = |field: &str| {
+            let id = field.replace('_', "-");
+            !matches.try_contains_id(&id).unwrap_or(false)
+                || matches.value_source(&id) != Some(ValueSource::CommandLine)
+        };
+
+        macro_rules! apply {
+            ($($group:ident . $field:ident),*) => {
+                $(
+                    if let Some(value) = self.$field {
+                        if from_file(stringify!($field)) {
+                            args.$group.$field = value;
+                        }
+                    }
+                )*
+            };
+        }
+        macro_rules! apply_some {
+            ($($group:ident . $field:ident),*) => {
+                $(
+                    if let Some(value) = self.$field {
+                        if from_file(stringify!($field)) {
+                            args.$group.$field = Some(value);
+                        }
+                    }
+                )*
+            };
+        }
+        macro_rules! apply_size {
+            ($($group:ident . $field:ident),*) => {
+                $(
+                    if let Some(size) = self.$field {
+                        if from_file(stringify!($field)) {
+                            args.$group.$field = size.bytes()?;
+                        }
+                    }
+                )*
+            };
+        }
+
+        apply!(
+            files.root,
+            rules.rules_ext,
+            rules.namespace,
+            rules.strict_rules,
+            pool.workers,
+            rules.scan_timeout,
+            processes.pid,
+            processes.user,
+            processes.process_name,
+            monitor.exec_monitor,
+            monitor.exec_poll_interval,
+            monitor.queue_size,
+            files.ext,
+            files.exclude,
+            files.no_default_excludes,
+            actions.action_timeout,
+            actions.quarantine,
+            quarantine.quarantine_dir,
+            files.archives,
+            files.archive_max_depth,
+            files.archive_max_members,
+            files.hash_all,
+            report.output,
+            serve.listen,
+            serve.allow_path_scan,
+            serve.allow_remote,
+            daemon.socket
+        );
+        apply_some!(
+            rules.rules,
+            rules.compiled_rules,
+            rules.save_compiled,
+            files.cache,
+            report.output_file
+        );
+        apply_size!(
+            files.min_file_size,
+            files.archive_max_size,
+            serve.max_body_size,
+            daemon.max_stream_size
+        );
+
+        if let Some(size) = self.max_file_size {
+            if from_file("max_file_size") {
+                args.files.max_file_size = Some(size.bytes()?);
+            }
+        }
+        if let Some(action) = self.action {
+            if from_file("action") {
+                args.actions.action = action
+                    .iter()
+                    .map(|spec| parse_action(spec))
+                    .collect::<Result<_, _>>()?;
+            }
+        }
+        if let Some(define) = self.define {
+            if from_file("define") {
+                if let Some(name) = define
+                    .keys()
+                    .find(|name| BUILTIN_EXTERNALS.contains(&name.as_str()))
+                {
+                    return Err(format!("'{}' is set for each scanned file", name));
+                }
+                args.rules.define = define.into_iter().collect();
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Loads the configuration file, if any, for the arguments not passed on the command line.
+pub(crate) fn load(
+    path: Option<&String>,
+    args: &mut Arguments,
+    matches: &ArgMatches,
+) -> Result<(), String> {
+    let path = match path {
+        Some(path) => path.clone(),
+        None if Path::new(DEFAULT_CONFIG_PATH).exists() => DEFAULT_CONFIG_PATH.to_string(),
+        None => return Ok(()),
+    };
+
+    log::debug!("loading configuration from {} ...", &path);
+
+    FileConfig::load(&path)?.apply(args, matches)
+}
+
+/// Validates the effective settings without running anything.
+pub(crate) fn check(args: &Arguments) -> Result<(), String> {
+    if let Some(root) = args
+        .files
+        .root
+        .iter()
+        .find(|root| !Path::new(root).exists())
+    {
+        return Err(format!("root path '{}' does not exist", root));
+    }
+
+    match &args.rules.rules {
+        Some(rules) if !Path::new(rules).exists() => {
+            return Err(format!("rules path '{}' does not exist", rules))
+        }
+        Some(_) => {}
+        None => return Err("no rules path, use --rules or set 'rules'".to_string()),
+    }
+
+    let files = &args.files;
+    if files
+        .max_file_size
+        .is_some_and(|max| max < files.min_file_size)
+    {
+        return Err("max-file-size is smaller than min-file-size".to_string());
+    }
+
+    if args.report.output_file.is_some() && args.report.output != OutputArg::Json {
+        return Err("output-file can only be used with json output".to_string());
+    }
+
+    args.options().check()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches};
+
+    use crate::args::Cli;
+
+    // the arguments of the command line with the configuration file applied, like main does
+    fn effective(config: &str, command_line: &[&str]) -> Result<Arguments, String> {
+        let matches = Cli::command()
+            .try_get_matches_from(command_line)
+            .map_err(|e| e.to_string())?;
+        let cli = Cli::from_arg_matches(&matches).map_err(|e| e.to_string())?;
+        let matches = match matches.subcommand() {
+            Some((_, sub_matches)) => sub_matches,
+            None => &matches,
+        };
+
+        let mut args = cli.arguments();
+        toml::from_str::<FileConfig>(config)
+            .map_err(|e| e.to_string())?
+            .apply(&mut args, matches)?;
+
+        Ok(args)
+    }
+
+    const CONFIG: &str = r#"
+root = ["/srv"]
+rules = "/etc/sauron/rules"
+workers = 4
+max-file-size = "1M"
+action = ["tag:ransomware=quarantine"]
+output = "json"
+
+[define]
+env = "prod"
+"#;
+
+    #[test]
+    fn file_overrides_defaults() {
+        let args = effective(CONFIG, &["sauron", "scan"]).unwrap();
+
+        assert_eq!(args.files.root, vec!["/srv"]);
+        assert_eq!(args.rules.rules.as_deref(), Some("/etc/sauron/rules"));
+        assert_eq!(args.pool.workers, 4);
+        assert_eq!(args.files.max_file_size, Some(1024 * 1024));
+        assert_eq!(args.actions.action.len(), 1);
+        assert_eq!(args.report.output, OutputArg::Json);
+        assert_eq!(args.rules.define.len(), 1);
+        // not in the file
+        assert_eq!(args.rules.scan_timeout, 30);
+    }
+
+    #[test]
+    fn command_line_overrides_file() {
+        let args = effective(
+            CONFIG,
+            &[
+                "sauron",
+                "scan",
+                "--root",
+                "/home",
+                "--rules",
+                "./rules",
+                "--workers",
+                "32",
+                "--max-file-size",
+                "2M",
+                "--action",
+                "log",
+                "--output",
+                "text",
+                "--define",
+                "env=dev",
+                "--define",
+                "level=3",
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(args.files.root, vec!["/home"]);
+        assert_eq!(args.rules.rules.as_deref(), Some("./rules"));
+        // even when passed with its default value
+        assert_eq!(args.pool.workers, 32);
+        assert_eq!(args.files.max_file_size, Some(2 * 1024 * 1024));
+        assert_eq!(
+            FileConfig::from_args(&args).action,
+            Some(vec!["log".to_string()])
+        );
+        assert_eq!(args.report.output, OutputArg::Text);
+        assert_eq!(args.rules.define.len(), 2);
+    }
+
+    #[test]
+    fn command_line_overrides_file_in_legacy_mode() {
+        let args = effective(
+            CONFIG,
+            &["sauron", "--workers", "8", "--max-file-size", "2M"],
+        )
+        .unwrap();
+
+        assert_eq!(args.pool.workers, 8);
+        assert_eq!(args.files.max_file_size, Some(2 * 1024 * 1024));
+        assert_eq!(args.files.root, vec!["/srv"]);
+    }
+
+    #[test]
+    fn rejects_invalid_file_values() {
+        assert!(effective("action = [\"kill\"]", &["sauron", "scan"]).is_err());
+        assert!(effective("max-file-size = \"1X\"", &["sauron", "scan"]).is_err());
+        assert!(effective("[define]\nfilename = \"x\"", &["sauron", "scan"]).is_err());
+        assert!(effective("unknown = 1", &["sauron", "scan"]).is_err());
+    }
+}
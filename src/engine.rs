@@ -1,117 +1,1187 @@
-use std::path::PathBuf;
-use std::time::Instant;
+use std::fmt;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize, Serializer};
+use sha2::{Digest, Sha256};
 use walkdir::WalkDir;
-use yara::{Compiler, Rules};
+use yara::{
+    CallbackMsg, CallbackReturn, Compiler, IoError, IoErrorKind, Metadata, MetadataValue, Rule,
+    Rules, Scanner,
+};
+
+use crate::hashes::Hashes;
 
 pub type Error = String;
 pub type Tag = String;
 
-#[derive(Clone, Debug)]
+// maximum number of bytes of matched data kept for each string match
+const MAX_SNIPPET_SIZE: usize = 64;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MetaValue {
+    Integer(i64),
+    String(String),
+    Boolean(bool),
+}
+
+impl fmt::Display for MetaValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetaValue::Integer(i) => write!(f, "{}", i),
+            MetaValue::String(s) => write!(f, "{:?}", s),
+            MetaValue::Boolean(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct StringMatch {
+    /// String identifier, including the leading '$'.
+    pub identifier: String,
+    /// Offset of the match in the scanned data.
+    pub offset: usize,
+    /// Length of the whole match.
+    pub length: usize,
+    /// Matched data, truncated to MAX_SNIPPET_SIZE bytes.
+    #[serde(serialize_with = "serialize_snippet")]
+    pub snippet: Vec<u8>,
+}
+
+impl fmt::Display for StringMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at 0x{:x}: \"{}\"",
+            &self.identifier,
+            self.offset,
+            self.snippet.escape_ascii()
+        )?;
+        if self.length > self.snippet.len() {
+            write!(f, " ({} bytes)", self.length)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RuleMatch {
+    pub namespace: String,
+    pub identifier: String,
+    pub tags: Vec<Tag>,
+    #[serde(serialize_with = "serialize_metadata")]
+    pub metadata: Vec<(String, MetaValue)>,
+    pub strings: Vec<StringMatch>,
+}
+
+impl RuleMatch {
+    fn from_rule(rule: &Rule) -> Self {
+        let mut strings = vec![];
+        for string in &rule.strings {
+            for m in &string.matches {
+                strings.push(StringMatch {
+                    identifier: string.identifier.to_string(),
+                    offset: m.offset,
+                    length: m.length,
+                    snippet: m.data.iter().take(MAX_SNIPPET_SIZE).copied().collect(),
+                });
+            }
+        }
+
+        RuleMatch {
+            namespace: rule.namespace.to_string(),
+            identifier: rule.identifier.to_string(),
+            tags: rule.tags.iter().map(|t| t.to_string()).collect(),
+            metadata: convert_metadata(&rule.metadatas),
+            strings,
+        }
+    }
+}
+
+fn convert_metadata(metadatas: &[Metadata]) -> Vec<(String, MetaValue)> {
+    metadatas
+        .iter()
+        .map(|meta| {
+            let value = match &meta.value {
+                MetadataValue::Integer(i) => MetaValue::Integer(*i),
+                MetadataValue::String(s) => MetaValue::String(s.to_string()),
+                MetadataValue::Boolean(b) => MetaValue::Boolean(*b),
+            };
+            (meta.identifier.to_string(), value)
+        })
+        .collect()
+}
+
+impl fmt::Display for RuleMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", &self.namespace, &self.identifier)?;
+        if !self.tags.is_empty() {
+            write!(f, " [{}]", self.tags.join(", "))?;
+        }
+        for (key, value) in &self.metadata {
+            write!(f, "\n    {} = {}", key, value)?;
+        }
+        for string in &self.strings {
+            write!(f, "\n    {}", string)?;
+        }
+        Ok(())
+    }
+}
+
+/// A running process whose memory has been scanned.
+#[derive(Clone, Debug, Serialize)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub uid: Option<u32>,
+    pub name: String,
+    pub exe: Option<PathBuf>,
+    pub cmdline: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
 pub struct Detection {
+    pub path: PathBuf,
+    pub size: u64,
     pub error: Option<Error>,
     pub detected: bool,
     pub tags: Vec<Tag>,
+    pub matches: Vec<RuleMatch>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hashes: Option<Hashes>,
+    /// Detections for the files contained in this one, if it's an archive.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub members: Vec<Detection>,
+    /// The process this detection is for, if its memory has been scanned instead of a file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub process: Option<ProcessInfo>,
+    #[serde(rename = "scan_duration", serialize_with = "serialize_duration")]
+    pub elapsed: Duration,
+}
+
+impl Detection {
+    fn new(path: PathBuf) -> Self {
+        Detection {
+            path,
+            size: 0,
+            error: None,
+            detected: false,
+            tags: vec![],
+            matches: vec![],
+            hashes: None,
+            members: vec![],
+            process: None,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    /// Rules matched by this file and by the files it contains.
+    pub fn all_matches(&self) -> Vec<&RuleMatch> {
+        let mut matches: Vec<&RuleMatch> = self.matches.iter().collect();
+        for member in &self.members {
+            matches.extend(member.all_matches());
+        }
+        matches
+    }
+}
+
+fn serialize_snippet<S: Serializer>(snippet: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&snippet.escape_ascii())
+}
+
+fn serialize_metadata<S: Serializer>(
+    metadata: &[(String, MetaValue)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(Some(metadata.len()))?;
+    for (key, value) in metadata {
+        map.serialize_entry(key, value)?;
+    }
+    map.end()
+}
+
+pub(crate) fn serialize_duration<S: Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+/// Rule file extensions loaded when none are configured.
+pub const DEFAULT_RULES_EXTENSIONS: &[&str] = &["yar", "yara", "rule", "yarc"];
+
+/// External variables set for each scanned file, declared as empty strings when compiling.
+pub const BUILTIN_EXTERNALS: &[&str] = &["filename", "filepath", "extension", "filetype"];
+
+// number of bytes read from the beginning of a file to tell its type
+const FILE_HEADER_SIZE: usize = 16;
+
+// compiled YARA rules files start with this magic
+const COMPILED_RULES_MAGIC: &[u8] = b"YARA";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NamespaceMode {
+    /// Load every rule file in the default namespace.
+    Default,
+    /// Load each rule file in its own namespace.
+    File,
+    /// Load each top-level subfolder of the rules path in its own namespace.
+    Folder,
 }
 
 pub struct Configuration {
+    /// Rules file or folder.
     pub data_path: String,
+    /// Scan timeout in seconds.
     pub timeout: i32,
+    /// Extensions of the rule files to load, DEFAULT_RULES_EXTENSIONS if empty.
+    pub extensions: Vec<String>,
+    pub namespace: NamespaceMode,
+    /// Load precompiled rules from this file if they are up to date with the sources.
+    pub compiled_rules: Option<String>,
+    /// Save the compiled rules to this file.
+    pub save_compiled: Option<String>,
+    /// Fail on the first invalid rule file instead of skipping it.
+    pub strict: bool,
+    /// Files smaller than this amount of bytes are not scanned, empty files never are.
+    pub min_file_size: u64,
+    /// Files bigger than this amount of bytes are not scanned.
+    pub max_file_size: Option<u64>,
+    /// Additional external variables defined when compiling the rules.
+    pub externals: Vec<(String, MetaValue)>,
+}
+
+// values of the builtin external variables for the scanned data
+struct Externals {
+    filename: String,
+    // folder containing the file
+    filepath: String,
+    // lowercase, including the leading dot
+    extension: String,
+    filetype: &'static str,
+}
+
+impl Externals {
+    fn of(path: &Path, header: &[u8]) -> Self {
+        let lossy = |s: &std::ffi::OsStr| s.to_string_lossy().to_string();
+
+        Externals {
+            filename: path.file_name().map(lossy).unwrap_or_default(),
+            filepath: path
+                .parent()
+                .map(|p| lossy(p.as_os_str()))
+                .unwrap_or_default(),
+            extension: path
+                .extension()
+                .map(|e| format!(".{}", lossy(e).to_lowercase()))
+                .unwrap_or_default(),
+            filetype: file_type(header),
+        }
+    }
+
+    fn define(&self, scanner: &mut Scanner) {
+        for (name, value) in [
+            ("filename", self.filename.as_str()),
+            ("filepath", self.filepath.as_str()),
+            ("extension", self.extension.as_str()),
+            ("filetype", self.filetype),
+        ] {
+            // precompiled rules might not declare them
+            if let Err(e) = scanner.define_variable(name, value) {
+                log::trace!("can't set external '{}': {:?}", name, e);
+            }
+        }
+    }
+}
+
+// tell the type of a file from its first bytes
+fn file_type(header: &[u8]) -> &'static str {
+    const MAGICS: &[(&[u8], &str)] = &[
+        (b"MZ", "EXE"),
+        (b"\x7fELF", "ELF"),
+        (b"\xcf\xfa\xed\xfe", "MACHO"),
+        (b"\xce\xfa\xed\xfe", "MACHO"),
+        (b"\xca\xfe\xba\xbe", "MACHO"),
+        (b"PK\x03\x04", "ZIP"),
+        (b"\x1f\x8b", "GZIP"),
+        (b"%PDF", "PDF"),
+        (b"{\\rtf", "RTF"),
+        (b"\xd0\xcf\x11\xe0", "OLE"),
+        (b"#!", "SCRIPT"),
+    ];
+
+    MAGICS
+        .iter()
+        .find(|(magic, _)| header.starts_with(magic))
+        .map(|(_, file_type)| *file_type)
+        .unwrap_or_default()
+}
+
+// read the first bytes of a file, if possible
+fn file_header(path: &Path) -> Vec<u8> {
+    let mut header = vec![];
+    if let Ok(file) = std::fs::File::open(path) {
+        let _ = file.take(FILE_HEADER_SIZE as u64).read_to_end(&mut header);
+    }
+    header
+}
+
+// a rule file and the namespace its rules are loaded into
+#[derive(Clone, Debug)]
+struct RuleFile {
+    path: PathBuf,
+    namespace: String,
+    // precompiled rules can't be added to a compiler and are loaded as a separate set
+    compiled: bool,
+}
+
+// the compiled sets of rules along with the fingerprint of their sources
+struct RuleSet {
+    rules: Vec<Rules>,
+    fingerprint: String,
+    // every rule of the sets, without string matches
+    loaded: Vec<RuleMatch>,
+    num_rules: usize,
+    // rule files that failed to compile or load and were skipped
+    skipped: usize,
 }
 
 pub struct Engine {
     config: Configuration,
-    rules: Rules,
+    // swapped atomically on reload, scans keep a reference to the set they started with
+    rules: RwLock<Arc<RuleSet>>,
 }
 
 impl Engine {
     pub fn new(config: Configuration) -> Result<Self, Error> {
         log::info!("initializing yara engine from '{}' ...", &config.data_path);
 
-        // create YARA compiler
-        let mut compiler = Compiler::new().map_err(|e| e.to_string())?;
-        let mut num_rules = 0;
-
-        // a single yara file has been passed as argument
-        if config.data_path.ends_with(".yar") {
-            log::debug!("loading {} ...", &config.data_path);
-            compiler = compiler
-                .add_rules_file(&config.data_path)
-                .map_err(|e| format!("could not load {:?}: {:?}", &config.data_path, e))?;
-            num_rules += 1;
-        } else {
-            // loop rules folder and load each .yar file
-            for entry in WalkDir::new(&config.data_path)
-                .follow_links(true)
-                .into_iter()
-                .filter_map(|e| e.ok())
-            {
-                let f_name = entry.path().to_string_lossy();
-                if f_name.ends_with(".yar") {
-                    log::debug!("loading {} ...", &f_name);
-                    compiler = compiler
-                        .add_rules_file(&*f_name)
-                        .map_err(|e| format!("could not load {:?}: {:?}", f_name, e))?;
-                    num_rules += 1;
+        let sources = Self::rule_files(&config);
+        let fingerprint = Self::fingerprint_sources(&config, &sources)?;
+        let rules = Self::load(&config, &sources, fingerprint)?;
+
+        log::info!("{} rules loaded", rules.num_rules);
+
+        Ok(Engine {
+            config,
+            rules: RwLock::new(Arc::new(rules)),
+        })
+    }
+
+    /// Recompile the rules from the sources and swap them in, keeping the current ones on error,
+    /// if no rule was loaded or if more rule files were skipped than with the current ones.
+    pub fn reload(&self) -> Result<(), Error> {
+        let current = self.rules();
+
+        let sources = Self::rule_files(&self.config);
+        let fingerprint = Self::fingerprint_sources(&self.config, &sources)?;
+        if fingerprint == current.fingerprint {
+            log::debug!("rules did not change, skipping reload");
+            return Ok(());
+        }
+
+        log::info!("reloading rules from '{}' ...", &self.config.data_path);
+
+        let rules = Self::load(&self.config, &sources, fingerprint)?;
+
+        // the rules folder might be briefly empty or contain half written files while updated
+        if rules.num_rules == 0 {
+            return Err(format!("no rules loaded from '{}'", &self.config.data_path));
+        }
+        if rules.skipped > current.skipped {
+            return Err(format!(
+                "{} rule files skipped because of errors, {} before",
+                rules.skipped, current.skipped
+            ));
+        }
+
+        log::info!(
+            "rules reloaded: {} -> {} ({:+})",
+            current.num_rules,
+            rules.num_rules,
+            rules.num_rules as i64 - current.num_rules as i64
+        );
+
+        *self.rules.write().unwrap() = Arc::new(rules);
+
+        Ok(())
+    }
+
+    /// Path the rules are loaded from.
+    pub fn rules_path(&self) -> &str {
+        &self.config.data_path
+    }
+
+    /// Fingerprint of the sources of the rules currently loaded.
+    pub fn fingerprint(&self) -> String {
+        self.rules().fingerprint.clone()
+    }
+
+    /// Minimum and maximum size of the files being scanned.
+    pub(crate) fn size_limits(&self) -> (u64, Option<u64>) {
+        (self.config.min_file_size, self.config.max_file_size)
+    }
+
+    /// The rules currently loaded.
+    pub fn loaded_rules(&self) -> Vec<RuleMatch> {
+        self.rules().loaded.clone()
+    }
+
+    fn rules(&self) -> Arc<RuleSet> {
+        self.rules.read().unwrap().clone()
+    }
+
+    fn load(
+        config: &Configuration,
+        sources: &[RuleFile],
+        fingerprint: String,
+    ) -> Result<RuleSet, Error> {
+        let (precompiled, sources): (Vec<RuleFile>, Vec<RuleFile>) =
+            sources.iter().cloned().partition(|f| f.compiled);
+
+        let mut rules = vec![];
+        let mut skipped = 0;
+
+        if !sources.is_empty() {
+            let (mut compiled, failed, loaded) = match Self::load_compiled(config, &fingerprint) {
+                Some((compiled, failed)) => (compiled, failed, true),
+                None => {
+                    let (compiled, failed) = Self::compile(config, &sources)?;
+                    (compiled, failed, false)
+                }
+            };
+            skipped += failed;
+
+            if let Some(path) = &config.save_compiled {
+                // no need to save again what we just loaded
+                if !loaded || config.compiled_rules.as_ref() != Some(path) {
+                    Self::save_compiled(&mut compiled, path, &fingerprint, failed)?;
                 }
             }
+
+            rules.push(compiled);
         }
 
-        // compile all rules
-        log::debug!("compiling {} rules ...", num_rules);
+        for file in &precompiled {
+            log::debug!("loading precompiled {:?} ...", &file.path);
+            match Rules::load_from_file(&file.path.to_string_lossy()) {
+                Ok(compiled) => rules.push(compiled),
+                Err(e) if !config.strict => {
+                    log::warn!("skipping {:?}: {:?}", &file.path, e);
+                    skipped += 1;
+                }
+                Err(e) => return Err(format!("could not load {:?}: {:?}", &file.path, e)),
+            }
+        }
+
+        let mut loaded = vec![];
+        for compiled in &rules {
+            loaded.extend(Self::list_rules(compiled)?);
+        }
+        let num_rules = loaded.len();
+
+        Ok(RuleSet {
+            rules,
+            fingerprint,
+            loaded,
+            num_rules,
+            skipped,
+        })
+    }
+
+    // the rules of a compiled set can't be iterated, but a scan reports each of them as either
+    // matching or not, so scan no data to list them (private rules are never reported)
+    fn list_rules(rules: &Rules) -> Result<Vec<RuleMatch>, Error> {
+        let mut listed = vec![];
+
+        rules
+            .scan_mem_callback(&[], 0, |message| {
+                if let CallbackMsg::RuleMatching(rule) | CallbackMsg::RuleNotMatching(rule) =
+                    message
+                {
+                    listed.push(RuleMatch::from_rule(&rule));
+                }
+                CallbackReturn::Continue
+            })
+            .map_err(|e| format!("can't list the loaded rules: {:?}", e))?;
+
+        Ok(listed)
+    }
+
+    // return the sorted list of rule files to load
+    fn rule_files(config: &Configuration) -> Vec<RuleFile> {
+        let data_path = Path::new(&config.data_path);
+        let has_rules_ext = |path: &Path| match path.extension() {
+            Some(ext) => {
+                let ext = ext.to_string_lossy().to_lowercase();
+                if config.extensions.is_empty() {
+                    DEFAULT_RULES_EXTENSIONS.contains(&ext.as_str())
+                } else {
+                    config.extensions.iter().any(|e| e.to_lowercase() == ext)
+                }
+            }
+            None => false,
+        };
+
+        // a single rules file has been passed as argument
+        if data_path.is_file() {
+            return vec![Self::rule_file(config, data_path)];
+        }
+
+        // loop rules folder and collect each rules file
+        WalkDir::new(data_path)
+            .follow_links(true)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file() && has_rules_ext(e.path()))
+            // don't load our own compiled rules cache as a precompiled rules file
+            .filter(|e| {
+                let is = |p: &Option<String>| p.as_ref().map(Path::new) == Some(e.path());
+                !is(&config.compiled_rules) && !is(&config.save_compiled)
+            })
+            .map(|e| Self::rule_file(config, e.path()))
+            .collect()
+    }
+
+    fn rule_file(config: &Configuration, path: &Path) -> RuleFile {
+        // path relative to the rules folder, or just the file name for a single file
+        let relative = match path.strip_prefix(&config.data_path) {
+            Ok(relative) if !relative.as_os_str().is_empty() => relative,
+            _ => Path::new(path.file_name().unwrap_or(path.as_os_str())),
+        };
+
+        let namespace = match config.namespace {
+            NamespaceMode::Default => "default".to_string(),
+            NamespaceMode::File => relative.with_extension("").to_string_lossy().to_string(),
+            NamespaceMode::Folder => {
+                let mut components = relative.components();
+                match (components.next(), components.next()) {
+                    // the file is inside a subfolder
+                    (Some(folder), Some(_)) => folder.as_os_str().to_string_lossy().to_string(),
+                    // the file is at the top level
+                    _ => relative.with_extension("").to_string_lossy().to_string(),
+                }
+            }
+        };
+
+        let mut magic = [0u8; 4];
+        let compiled = std::fs::File::open(path)
+            .and_then(|mut f| f.read_exact(&mut magic))
+            .map(|_| magic == COMPILED_RULES_MAGIC)
+            .unwrap_or(false);
+
+        RuleFile {
+            path: path.to_path_buf(),
+            namespace,
+            compiled,
+        }
+    }
+
+    // hash paths, namespaces, sizes and contents of the rule files along with the externals
+    fn fingerprint_sources(config: &Configuration, sources: &[RuleFile]) -> Result<String, Error> {
+        let mut hasher = Sha256::new();
+
+        for (name, value) in &config.externals {
+            hasher.update(format!("{}={:?};", name, value).as_bytes());
+        }
+
+        for file in sources {
+            let path = &file.path;
+            let data =
+                std::fs::read(path).map_err(|e| format!("could not read {:?}: {:?}", path, e))?;
+
+            hasher.update(path.to_string_lossy().as_bytes());
+            hasher.update(file.namespace.as_bytes());
+            hasher.update(data.len().to_le_bytes());
+            hasher.update(&data);
+        }
+
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    // the fingerprint of the sources is stored alongside the compiled rules, followed by the
+    // number of rule files skipped when compiling them
+    fn fingerprint_path(compiled_path: &str) -> String {
+        format!("{}.fingerprint", compiled_path)
+    }
+
+    // return the compiled rules and the number of files skipped when they were compiled
+    fn load_compiled(config: &Configuration, fingerprint: &str) -> Option<(Rules, usize)> {
+        let path = config.compiled_rules.as_ref()?;
+
+        let skipped = match std::fs::read_to_string(Self::fingerprint_path(path)) {
+            Ok(saved) => {
+                let mut lines = saved.lines();
+                match (lines.next(), lines.next().map(|n| n.trim().parse())) {
+                    (Some(saved), Some(Ok(skipped))) if saved.trim() == fingerprint => skipped,
+                    _ => {
+                        log::info!("compiled rules in '{}' are outdated, recompiling ...", path);
+                        return None;
+                    }
+                }
+            }
+            Err(e) => {
+                log::info!(
+                    "can't read fingerprint for '{}' ({}), recompiling ...",
+                    path,
+                    e
+                );
+                return None;
+            }
+        };
 
         let start = Instant::now();
 
-        let rules = compiler.compile_rules().map_err(|e| e.to_string())?;
+        match Rules::load_from_file(path) {
+            Ok(rules) => {
+                log::info!(
+                    "compiled rules loaded from '{}' in {:?}",
+                    path,
+                    start.elapsed()
+                );
+                Some((rules, skipped))
+            }
+            Err(e) => {
+                log::warn!("can't load compiled rules from '{}': {:?}", path, e);
+                None
+            }
+        }
+    }
 
-        log::info!("{} rules compiled in {:?}", num_rules, start.elapsed());
+    fn save_compiled(
+        rules: &mut Rules,
+        path: &str,
+        fingerprint: &str,
+        skipped: usize,
+    ) -> Result<(), Error> {
+        log::info!("saving compiled rules to '{}' ...", path);
 
-        Ok(Engine { config, rules })
+        rules
+            .save(path)
+            .map_err(|e| format!("could not save compiled rules to '{}': {:?}", path, e))?;
+
+        std::fs::write(
+            Self::fingerprint_path(path),
+            format!("{}\n{}\n", fingerprint, skipped),
+        )
+        .map_err(|e| format!("could not save rules fingerprint for '{}': {:?}", path, e))
+    }
+
+    // return the compiled rules and the number of files skipped because of errors
+    fn compile(config: &Configuration, sources: &[RuleFile]) -> Result<(Rules, usize), Error> {
+        let total = sources.len();
+        let sources = if config.strict {
+            sources.to_vec()
+        } else {
+            Self::valid_rule_files(config, sources)
+        };
+
+        let start = Instant::now();
+
+        // a YARA compiler can't be used anymore once it failed, in lenient mode start
+        // over without the offending file whenever one fails in combination with the others
+        let mut sources = sources;
+        let rules = loop {
+            match Self::compile_files(config, &sources) {
+                Ok(rules) => break rules,
+                Err((Some(index), error)) if !config.strict => {
+                    let file = sources.remove(index);
+                    Self::report_invalid(&file.path, &error);
+                }
+                Err((Some(index), error)) => {
+                    return Err(format!(
+                        "could not load {:?}: {:?}",
+                        &sources[index].path, error
+                    ))
+                }
+                Err((None, error)) => return Err(error.to_string()),
+            }
+        };
+
+        log::info!(
+            "{} rule files compiled in {:?}",
+            sources.len(),
+            start.elapsed()
+        );
+
+        Ok((rules, total - sources.len()))
+    }
+
+    // compile each file in isolation and return the ones that are valid
+    fn valid_rule_files(config: &Configuration, sources: &[RuleFile]) -> Vec<RuleFile> {
+        let mut valid = vec![];
+
+        for file in sources {
+            let res = Compiler::new()
+                .map_err(yara::Error::Yara)
+                .and_then(|mut compiler| {
+                    Self::define_externals(&mut compiler, config)?;
+                    Self::add_rule_file(compiler, file)
+                });
+            match res {
+                Ok(_) => valid.push(file.clone()),
+                Err(error) => Self::report_invalid(&file.path, &error),
+            }
+        }
+
+        if valid.len() < sources.len() {
+            log::warn!(
+                "{} of {} rule files skipped because of errors",
+                sources.len() - valid.len(),
+                sources.len()
+            );
+        }
+
+        valid
+    }
+
+    fn report_invalid(path: &Path, error: &yara::Error) {
+        match error {
+            yara::Error::Compile(errors) => {
+                for error in errors.iter() {
+                    match &error.filename {
+                        Some(filename) => log::warn!(
+                            "skipping {:?}: {}:{}: {}",
+                            path,
+                            filename,
+                            error.line,
+                            &error.message
+                        ),
+                        None => log::warn!(
+                            "skipping {:?}: line {}: {}",
+                            path,
+                            error.line,
+                            &error.message
+                        ),
+                    }
+                }
+            }
+            _ => log::warn!("skipping {:?}: {}", path, error),
+        }
+    }
+
+    // yara 0.15 frees the namespace before compiling the files added with
+    // add_rules_file_with_namespace, so add their source as a string instead
+    fn add_rule_file(mut compiler: Compiler, file: &RuleFile) -> Result<Compiler, yara::Error> {
+        let open_error = |e| yara::Error::Io(IoError::new(e, IoErrorKind::OpenRulesFile));
+
+        let source = std::fs::read_to_string(&file.path).map_err(open_error)?;
+        if source.contains('\0') {
+            return Err(open_error(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "rule file contains a null byte",
+            )));
+        }
+
+        // relative includes are then resolved from the folder of the file, like yara does for files
+        let folder = file
+            .path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        compiler.set_include_callback(move |name, _, _| {
+            std::fs::read_to_string(folder.join(name)).ok()
+        });
+
+        compiler.add_rules_str_with_namespace(&source, &file.namespace)
+    }
+
+    // externals have to be declared before compiling the rules using them
+    fn define_externals(
+        compiler: &mut Compiler,
+        config: &Configuration,
+    ) -> Result<(), yara::Error> {
+        for name in BUILTIN_EXTERNALS {
+            compiler
+                .define_variable(name, "")
+                .map_err(yara::Error::Yara)?;
+        }
+
+        for (name, value) in &config.externals {
+            match value {
+                MetaValue::Integer(i) => compiler.define_variable(name, *i),
+                MetaValue::String(s) => compiler.define_variable(name, s.as_str()),
+                MetaValue::Boolean(b) => compiler.define_variable(name, *b),
+            }
+            .map_err(yara::Error::Yara)?;
+        }
+
+        Ok(())
+    }
+
+    // on error return the index of the file that failed, if any
+    fn compile_files(
+        config: &Configuration,
+        sources: &[RuleFile],
+    ) -> Result<Rules, (Option<usize>, yara::Error)> {
+        // create YARA compiler
+        let mut compiler = Compiler::new().map_err(|e| (None, yara::Error::Yara(e)))?;
+
+        Self::define_externals(&mut compiler, config).map_err(|e| (None, e))?;
+
+        for (index, file) in sources.iter().enumerate() {
+            log::debug!(
+                "loading {:?} in namespace '{}' ...",
+                &file.path,
+                &file.namespace
+            );
+            compiler = Self::add_rule_file(compiler, file).map_err(|e| (Some(index), e))?;
+        }
+
+        // compile all rules
+        log::debug!("compiling {} rule files ...", sources.len());
+
+        compiler
+            .compile_rules()
+            .map_err(|e| (None, yara::Error::Yara(e)))
+    }
+
+    /// Empty data and data outside of the configured size limits is not scanned.
+    pub(crate) fn is_scannable(&self, path: &Path, size: u64) -> bool {
+        if size == 0 {
+            log::trace!("ignoring empty file {:?}", path);
+        } else if size < self.config.min_file_size {
+            log::trace!("ignoring {:?}, {} bytes is below the minimum", path, size);
+        } else if self.config.max_file_size.is_some_and(|max| size > max) {
+            log::trace!("ignoring {:?}, {} bytes is above the maximum", path, size);
+        } else {
+            return true;
+        }
+        false
     }
 
     pub fn scan(&self, path: &PathBuf) -> Detection {
-        let mut detected = false;
-        let mut tags = vec![];
-        let mut error: Option<Error> = None;
+        let start = Instant::now();
+        let mut detection = Detection::new(path.clone());
 
         // get file metadata
-        match std::fs::metadata(&path) {
+        match std::fs::metadata(path) {
             Ok(data) => {
-                // skip empty files
-                let file_size = data.len();
-                if file_size == 0 {
-                    log::trace!("ignoring empty file {:?}", &path);
-                } else {
-                    let start = Instant::now();
-
+                detection.size = data.len();
+                if self.is_scannable(path, detection.size) {
                     // scan this file with the loaded YARA rules
-                    match self.rules.scan_file(path, self.config.timeout) {
-                        Ok(matches) => {
-                            if !matches.is_empty() {
-                                detected = true;
-                                for rule in matches {
-                                    tags.push(rule.identifier.to_string());
-                                }
-                            }
-                        }
-                        Err(e) => error = Some(format!("can't scan {:?}: {:?}", path, e)),
+                    let externals = Externals::of(path, &file_header(path));
+                    self.scan_with(&mut detection, &externals, |scanner| {
+                        scanner.scan_file(path).map_err(|e| format!("{:?}", e))
+                    });
+                }
+            }
+            Err(e) => detection.error = Some(format!("can't get metadata for {:?}: {:?}", path, e)),
+        }
+
+        detection.elapsed = start.elapsed();
+        detection
+    }
+
+    /// Scans data already in memory, regardless of the file size limits. The label is
+    /// reported as the path of the detection and used for the filename and extension externals.
+    pub fn scan_bytes<L: Into<PathBuf>>(&self, data: &[u8], label: L) -> Detection {
+        let start = Instant::now();
+        let mut detection = Detection::new(label.into());
+
+        detection.size = data.len() as u64;
+        if !data.is_empty() {
+            let externals =
+                Externals::of(&detection.path, &data[..data.len().min(FILE_HEADER_SIZE)]);
+            self.scan_with(&mut detection, &externals, |scanner| {
+                scanner.scan_mem(data).map_err(|e| format!("{:?}", e))
+            });
+        }
+
+        detection.elapsed = start.elapsed();
+        detection
+    }
+
+    /// Reads the data to scan until the end of the reader, like scan_bytes, except that data
+    /// outside of the file size limits is not scanned and reported as an error instead.
+    pub fn scan_reader<R: Read, L: Into<PathBuf>>(&self, mut reader: R, label: L) -> Detection {
+        let label = label.into();
+        let mut data = vec![];
+
+        // read one byte more than the maximum to tell if it's exceeded
+        let res = match self.config.max_file_size {
+            Some(max) => reader
+                .by_ref()
+                .take(max.saturating_add(1))
+                .read_to_end(&mut data),
+            None => reader.read_to_end(&mut data),
+        };
+
+        match res {
+            Ok(size) if self.is_scannable(&label, size as u64) => self.scan_bytes(&data, label),
+            Ok(size) => {
+                let mut detection = Detection::new(label);
+                detection.size = size as u64;
+                detection.error = Some(match self.config.max_file_size {
+                    Some(max) if size as u64 > max => {
+                        format!("more than {} bytes, not scanned", max)
                     }
+                    _ if size == 0 => "no data, not scanned".to_string(),
+                    _ => format!(
+                        "{} bytes is below the minimum of {}, not scanned",
+                        size, self.config.min_file_size
+                    ),
+                });
+                detection
+            }
+            Err(e) => {
+                let mut detection = Detection::new(label);
+                detection.error = Some(format!("can't read {:?}: {:?}", &detection.path, e));
+                detection
+            }
+        }
+    }
+
+    /// Scans the memory of a running process.
+    pub(crate) fn scan_process(&self, process: ProcessInfo) -> Detection {
+        let start = Instant::now();
+        let pid = process.pid;
+        let mut detection = Detection::new(PathBuf::from(format!("/proc/{}", pid)));
+
+        // describe the process by its executable
+        let exe = process.exe.clone().unwrap_or_default();
+        self.scan_with(&mut detection, &Externals::of(&exe, &[]), |scanner| {
+            scanner.scan_process(pid).map_err(|e| format!("{:?}", e))
+        });
+
+        detection.process = Some(process);
+        detection.elapsed = start.elapsed();
+        detection
+    }
 
-                    log::debug!(
-                        "{:?} - {} bytes scanned in {:?} ",
-                        path,
-                        file_size,
-                        start.elapsed()
-                    );
+    // run the scan function with each loaded set of rules and collect the matches
+    fn scan_with<F>(&self, detection: &mut Detection, externals: &Externals, scan: F)
+    where
+        F: for<'r> Fn(&mut Scanner<'r>) -> Result<Vec<Rule<'r>>, Error>,
+    {
+        let start = Instant::now();
+        let ruleset = self.rules();
+
+        for rules in &ruleset.rules {
+            let res = rules
+                .scanner()
+                .map_err(|e| format!("{:?}", e))
+                .and_then(|mut scanner| {
+                    scanner.set_timeout(self.config.timeout);
+                    externals.define(&mut scanner);
+                    scan(&mut scanner)
+                });
+
+            match res {
+                Ok(rules) => {
+                    if !rules.is_empty() {
+                        detection.detected = true;
+                        for rule in rules {
+                            detection.tags.push(rule.identifier.to_string());
+                            detection.matches.push(RuleMatch::from_rule(&rule));
+                        }
+                    }
+                }
+                Err(e) => {
+                    detection.error = Some(format!("can't scan {:?}: {}", &detection.path, e));
+                    break;
                 }
             }
-            Err(e) => error = Some(format!("can't get metadata for {:?}: {:?}", path, e)),
         }
 
-        Detection {
-            detected,
-            tags,
-            error,
+        log::debug!(
+            "{:?} - {} bytes scanned in {:?} ",
+            &detection.path,
+            detection.size,
+            start.elapsed()
+        );
+    }
+}
+
+/// A unique temporary path for the files of a test.
+#[cfg(test)]
+pub(crate) fn test_path(name: &str) -> PathBuf {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    std::env::temp_dir().join(format!(
+        "sauron-test-{}-{}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed),
+        name
+    ))
+}
+
+/// The engine configuration of the tests, loading the rules from the path.
+#[cfg(test)]
+pub(crate) fn test_configuration(data_path: &Path) -> Configuration {
+    Configuration {
+        data_path: data_path.to_string_lossy().to_string(),
+        timeout: 10,
+        extensions: vec![],
+        namespace: NamespaceMode::Default,
+        compiled_rules: None,
+        save_compiled: None,
+        strict: true,
+        min_file_size: 0,
+        max_file_size: None,
+        externals: vec![],
+    }
+}
+
+/// Compiles an engine from the source of a rules file, for the tests of the modules scanning with it.
+#[cfg(test)]
+pub(crate) fn test_engine(source: &str) -> Engine {
+    let path = test_path("rules.yar");
+    std::fs::write(&path, source).expect("can't write test rules");
+
+    let engine = Engine::new(test_configuration(&path)).expect("can't compile test rules");
+
+    let _ = std::fs::remove_file(&path);
+
+    engine
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const EVIL: &str = r#"rule evil { strings: $a = "EVILSTRING" condition: $a }"#;
+
+    // write the rule files of a test, named by their path relative to the rules folder
+    fn rules_folder(files: &[(&str, &str)]) -> PathBuf {
+        let folder = test_path("rules");
+        for (name, source) in files {
+            let path = folder.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
         }
+        folder
+    }
+
+    fn namespaces(engine: &Engine) -> Vec<String> {
+        engine
+            .loaded_rules()
+            .iter()
+            .map(|rule| format!("{}:{}", rule.namespace, rule.identifier))
+            .collect()
+    }
+
+    #[test]
+    fn loads_folders_in_their_namespace() {
+        // same identifier in both folders, only valid in separate namespaces
+        let folder = rules_folder(&[
+            ("a/r.yar", EVIL),
+            (
+                "b/r.yar",
+                r#"rule evil { strings: $a = "OTHERSTRING" condition: $a }"#,
+            ),
+        ]);
+        let engine = Engine::new(Configuration {
+            namespace: NamespaceMode::Folder,
+            ..test_configuration(&folder)
+        })
+        .unwrap();
+
+        assert_eq!(namespaces(&engine), vec!["a:evil", "b:evil"]);
+
+        let res = engine.scan_bytes(b"xxOTHERSTRINGxx", "data");
+        assert!(res.detected);
+        assert_eq!(res.matches.len(), 1);
+        assert_eq!(res.matches[0].namespace, "b");
+
+        let _ = fs::remove_dir_all(folder);
+    }
+
+    #[test]
+    fn loads_files_in_their_namespace() {
+        let folder = rules_folder(&[("one.yar", EVIL), ("two.yara", EVIL)]);
+        let engine = Engine::new(Configuration {
+            namespace: NamespaceMode::File,
+            ..test_configuration(&folder)
+        })
+        .unwrap();
+
+        assert_eq!(namespaces(&engine), vec!["one:evil", "two:evil"]);
+
+        let _ = fs::remove_dir_all(folder);
+    }
+
+    #[test]
+    fn rejects_colliding_identifiers_in_one_namespace() {
+        let folder = rules_folder(&[("a/r.yar", EVIL), ("b/r.yar", EVIL)]);
+
+        assert!(Engine::new(test_configuration(&folder)).is_err());
+
+        let _ = fs::remove_dir_all(folder);
+    }
+
+    #[test]
+    fn keeps_skipped_files_count_of_compiled_rules() {
+        let folder = rules_folder(&[("evil.yar", EVIL), ("broken.yar", "rule broken {")]);
+        let cache = folder.join("rules.cache").to_string_lossy().to_string();
+        let config = || Configuration {
+            strict: false,
+            compiled_rules: Some(cache.clone()),
+            save_compiled: Some(cache.clone()),
+            ..test_configuration(&folder)
+        };
+
+        assert_eq!(Engine::new(config()).unwrap().rules().skipped, 1);
+
+        // loaded from the compiled rules, still skipping the broken file
+        let engine = Engine::new(config()).unwrap();
+        assert_eq!(engine.rules().skipped, 1);
+
+        // so that adding a rule doesn't look like more files failing
+        fs::write(folder.join("new.yar"), "rule new { condition: true }").unwrap();
+        engine.reload().unwrap();
+        assert_eq!(engine.rules().num_rules, 2);
+
+        let _ = fs::remove_dir_all(folder);
+    }
+
+    #[test]
+    fn resolves_includes_from_the_rule_file_folder() {
+        let folder = rules_folder(&[
+            (
+                "a/r.yar",
+                "include \"common.inc\"\nrule uses { condition: helper }",
+            ),
+            ("a/common.inc", "rule helper { condition: true }"),
+        ]);
+        let engine = Engine::new(Configuration {
+            namespace: NamespaceMode::Folder,
+            ..test_configuration(&folder)
+        })
+        .unwrap();
+
+        assert_eq!(namespaces(&engine), vec!["a:helper", "a:uses"]);
+
+        let _ = fs::remove_dir_all(folder);
+    }
+
+    #[test]
+    fn reports_data_over_the_maximum_as_an_error() {
+        let folder = rules_folder(&[("r.yar", EVIL)]);
+        let engine = Engine::new(Configuration {
+            max_file_size: Some(1024),
+            ..test_configuration(&folder)
+        })
+        .unwrap();
+
+        let mut data = vec![b'x'; 2000];
+        data.extend_from_slice(b"EVILSTRING");
+        let res = engine.scan_reader(&data[..], "stdin");
+        assert!(!res.detected);
+        assert!(res.error.is_some());
+
+        let res = engine.scan_reader(&data[1000..], "stdin");
+        assert!(res.detected);
+        assert!(res.error.is_none());
+
+        let _ = fs::remove_dir_all(folder);
     }
 }
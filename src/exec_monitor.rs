@@ -0,0 +1,148 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, Metadata};
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use threadpool::ThreadPool;
+
+use crate::engine::Engine;
+use crate::fs_monitor::Callback;
+use crate::hashes::sha256_file;
+use crate::pipeline::Pipeline;
+use crate::proc_scan;
+
+// identifies a version of an executable without reading it
+type ExeId = (u64, u64, u64, i64);
+
+fn exe_id(metadata: &Metadata) -> ExeId {
+    (
+        metadata.dev(),
+        metadata.ino(),
+        metadata.size(),
+        metadata.mtime(),
+    )
+}
+
+/// Polls /proc for new processes and scans their executable and memory, once per executable.
+pub(crate) fn start(
+    engine: Arc<Engine>,
+    pipeline: Arc<Pipeline>,
+    pool: ThreadPool,
+    interval: Duration,
+    callback: Callback,
+) {
+    log::info!("monitoring new processes every {:?} ...", interval);
+
+    thread::spawn(move || {
+        let own_pid = std::process::id();
+        // executable of each running process, a process calling exec keeps its pid
+        let mut known: HashMap<u32, Option<PathBuf>> = HashMap::new();
+        // executables already scanned with the current rules, by version and by content
+        let mut scanned_ids: HashSet<ExeId> = HashSet::new();
+        let mut scanned: HashSet<String> = HashSet::new();
+        let mut fingerprint = engine.fingerprint();
+        // processes already running at startup are not scanned
+        let mut first = true;
+
+        loop {
+            let pids = match proc_scan::pids() {
+                Ok(pids) => pids,
+                Err(e) => {
+                    log::error!("exec monitoring error: {}", e);
+                    thread::sleep(interval);
+                    continue;
+                }
+            };
+
+            // scan again executables already seen once the rules changed
+            let current = engine.fingerprint();
+            if current != fingerprint {
+                log::debug!(
+                    "rules changed, forgetting {} scanned executables",
+                    scanned.len()
+                );
+                scanned_ids.clear();
+                scanned.clear();
+                fingerprint = current;
+            }
+
+            let mut running = HashMap::with_capacity(pids.len());
+            for pid in pids {
+                // can't be read for kernel threads or, without privileges, other users' processes
+                let proc_exe = PathBuf::from(format!("/proc/{}/exe", pid));
+                let exe = fs::read_link(&proc_exe).ok();
+                let is_new = known.get(&pid) != Some(&exe);
+
+                running.insert(pid, exe.clone());
+
+                if first || !is_new || pid == own_pid || exe.is_none() {
+                    continue;
+                }
+
+                // go through /proc, the executable might have been deleted or replaced
+                let id = match fs::metadata(&proc_exe) {
+                    Ok(metadata) => exe_id(&metadata),
+                    Err(e) => {
+                        log::debug!("can't stat {:?}: {:?}", &proc_exe, e);
+                        continue;
+                    }
+                };
+                if scanned_ids.contains(&id) {
+                    log::trace!("executable of process {} {:?} already scanned", pid, &exe);
+                    continue;
+                }
+
+                // only hash new versions, to tell copies of an executable already scanned
+                let sha256 = match sha256_file(&proc_exe) {
+                    Ok(sha256) => sha256,
+                    Err(e) => {
+                        log::debug!("can't hash {:?}: {:?}", &proc_exe, e);
+                        continue;
+                    }
+                };
+                scanned_ids.insert(id);
+                if !scanned.insert(sha256) {
+                    log::trace!("executable of process {} {:?} already scanned", pid, &exe);
+                    continue;
+                }
+
+                let process = match proc_scan::process_info(pid) {
+                    Some(process) => process,
+                    None => continue,
+                };
+
+                log::debug!(
+                    "new process {} ({}) {:?}",
+                    pid,
+                    &process.name,
+                    &process.cmdline
+                );
+
+                // scan the executable by its path if it's still there so that actions apply to
+                // it, through /proc otherwise
+                let target = exe
+                    .filter(|exe| fs::metadata(exe).is_ok_and(|metadata| exe_id(&metadata) == id))
+                    .unwrap_or(proc_exe);
+
+                // create thread-safe references
+                let a_pipeline = pipeline.clone();
+                let callback = callback.clone();
+                // submit scan job to the threads pool
+                pool.execute(move || {
+                    if let Some(res) = a_pipeline.process(&target) {
+                        callback(&res);
+                    }
+                    callback(&a_pipeline.process_memory(process));
+                });
+            }
+
+            known = running;
+            first = false;
+
+            thread::sleep(interval);
+        }
+    });
+}
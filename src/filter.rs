@@ -0,0 +1,138 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use regex::Regex;
+
+use crate::options::Options;
+
+// pseudo filesystems that are never worth scanning
+const DEFAULT_EXCLUSIONS: &[&str] = &["/proc", "/sys", "/dev"];
+
+// prefix of exclusion patterns to be parsed as regular expressions instead of globs
+const REGEX_PREFIX: &str = "re:";
+
+/// Decides which paths are excluded from scanning and monitoring.
+pub(crate) struct Filter {
+    globs: GlobSet,
+    regexes: Vec<Regex>,
+    // lowercase extensions to scan, all if empty
+    extensions: Vec<String>,
+}
+
+impl Filter {
+    pub fn new(exclusions: &[String], extensions: &[String]) -> Result<Self, String> {
+        let mut globs = GlobSetBuilder::new();
+        let mut regexes = vec![];
+
+        for pattern in exclusions {
+            if let Some(expr) = pattern.strip_prefix(REGEX_PREFIX) {
+                regexes.push(
+                    Regex::new(expr)
+                        .map_err(|e| format!("invalid exclusion regex '{}': {}", expr, e))?,
+                );
+            } else {
+                globs.add(
+                    Glob::new(pattern)
+                        .map_err(|e| format!("invalid exclusion glob '{}': {}", pattern, e))?,
+                );
+            }
+        }
+
+        let globs = globs.build().map_err(|e| e.to_string())?;
+
+        let extensions = extensions.iter().map(|e| e.to_lowercase()).collect();
+
+        Ok(Filter {
+            globs,
+            regexes,
+            extensions,
+        })
+    }
+
+    pub fn from_options(options: &Options) -> Result<Self, String> {
+        let mut exclusions = options.exclude.clone();
+
+        if options.default_excludes {
+            exclusions.extend(DEFAULT_EXCLUSIONS.iter().map(|e| e.to_string()));
+        }
+
+        // never scan the caller's own files
+        for path in &options.skip_paths {
+            exclusions.push(Self::exact_path(path));
+        }
+
+        // nor our scan cache
+        if let Some(cache) = &options.cache {
+            exclusions.push(Self::exact_path(cache));
+        }
+
+        // never scan the quarantine
+        exclusions.push(Self::exact_path(&options.quarantine_dir));
+
+        for exclusion in &exclusions {
+            log::debug!("excluding {}", exclusion);
+        }
+
+        Self::new(&exclusions, &options.extensions)
+    }
+
+    // exclusion pattern matching exactly this path
+    fn exact_path(path: &str) -> String {
+        let path = fs::canonicalize(path)
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_else(|_| path.to_string());
+
+        format!("{}^{}$", REGEX_PREFIX, regex::escape(&path))
+    }
+
+    /// Returns true if this path matches an exclusion, used to prune whole subtrees while walking.
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        if self.globs.is_match(path) {
+            return true;
+        }
+
+        let path = path.to_string_lossy();
+        self.regexes.iter().any(|r| r.is_match(&path))
+    }
+
+    /// Returns true if no extensions filter was set or the path has one of the extensions.
+    pub fn has_allowed_ext(&self, path: &Path) -> bool {
+        if self.extensions.is_empty() {
+            return true;
+        }
+
+        match path.extension() {
+            Some(ext) => self
+                .extensions
+                .contains(&ext.to_string_lossy().to_lowercase()),
+            None => false,
+        }
+    }
+
+    /// Returns true if this path or any of its parent folders matches an exclusion.
+    pub fn is_excluded_tree(&self, path: &Path) -> bool {
+        path.ancestors().any(|p| self.is_excluded(p))
+    }
+}
+
+/// The root paths to walk or watch, without the ones already contained in another root.
+pub(crate) fn unique_roots<P: AsRef<Path>>(roots: &[P]) -> Vec<PathBuf> {
+    let mut roots: Vec<PathBuf> = roots
+        .iter()
+        .map(|root| fs::canonicalize(root).unwrap_or_else(|_| root.as_ref().to_path_buf()))
+        .collect();
+
+    // parents sort before their children
+    roots.sort();
+    roots.dedup();
+
+    let mut unique: Vec<PathBuf> = vec![];
+    for root in roots {
+        match unique.iter().find(|parent| root.starts_with(parent)) {
+            Some(parent) => log::debug!("{:?} is already included in {:?}", root, parent),
+            None => unique.push(root),
+        }
+    }
+    unique
+}
@@ -1,31 +1,178 @@
+use std::path::Path;
 use std::sync::mpsc::channel;
 use std::sync::Arc;
+use std::thread;
 use std::time::Duration;
 
 use notify::{watcher, DebouncedEvent, RecursiveMode, Watcher};
 use threadpool::ThreadPool;
 
-use crate::engine::Engine;
-use crate::Arguments;
+use crate::engine::{Detection, Engine, Error};
+use crate::filter::{unique_roots, Filter};
+use crate::options::Options;
+use crate::pipeline::Pipeline;
+use crate::queue::{Stats, WorkQueue};
+use crate::report::Reporter;
 
-pub(crate) fn start(args: Arguments, engine: Engine) -> Result<(), String> {
-    // create a recursive filesystem monitor for the root path
-    log::info!("initializing filesystem monitor for '{}' ...", &args.root);
+/// Receives the result of each file, or process, scanned while monitoring.
+pub(crate) type Callback = Arc<dyn Fn(&Detection) + Send + Sync>;
+
+// submit the queued paths to the pool, no more than one per worker at a time
+fn dispatch(queue: Arc<WorkQueue>, pool: ThreadPool, pipeline: Arc<Pipeline>, callback: Callback) {
+    thread::spawn(move || loop {
+        let work = queue.pop();
+
+        // create thread-safe references
+        let a_pipeline = pipeline.clone();
+        let callback = callback.clone();
+        // submit scan job to the threads pool
+        pool.execute(move || {
+            // perform the scanning
+            if let Some(res) = a_pipeline.process(&work.path) {
+                callback(&res);
+            }
+        });
+    });
+}
+
+// periodically log the scan queue counters, warning about dropped events
+fn log_queue_stats(queue: Arc<WorkQueue>) {
+    thread::spawn(move || {
+        let mut last = Stats::default();
+
+        loop {
+            thread::sleep(QUEUE_STATS_INTERVAL);
+
+            let stats = queue.stats();
+            if stats.dropped > last.dropped {
+                log::warn!(
+                    "scan queue full, {} events dropped (depth={} in_flight={} coalesced={} dropped={})",
+                    stats.dropped - last.dropped,
+                    stats.depth,
+                    stats.in_flight,
+                    stats.coalesced,
+                    stats.dropped
+                );
+            } else if stats != last {
+                log::debug!(
+                    "scan queue depth={} in_flight={} coalesced={} dropped={}",
+                    stats.depth,
+                    stats.in_flight,
+                    stats.coalesced,
+                    stats.dropped
+                );
+            }
+            last = stats;
+        }
+    });
+}
+
+// how long to wait for changes to the rules to settle before reloading them
+const RULES_RELOAD_DELAY: Duration = Duration::from_secs(2);
+
+// how often the scan queue counters are logged, if they changed
+const QUEUE_STATS_INTERVAL: Duration = Duration::from_secs(10);
+
+// watch the rules path and reload the engine whenever it changes
+fn watch_rules(rules_path: &str, engine: Arc<Engine>) -> Result<(), String> {
+    log::info!("watching '{}' for rules changes ...", rules_path);
 
     let (tx, rx) = channel();
-    let mut watcher = watcher(tx, Duration::ZERO).map_err(|e| e.to_string())?;
+    let mut watcher = watcher(tx, RULES_RELOAD_DELAY).map_err(|e| e.to_string())?;
 
     watcher
-        .watch(&args.root, RecursiveMode::Recursive)
+        .watch(rules_path, RecursiveMode::Recursive)
         .map_err(|e| e.to_string())?;
 
-    log::info!("initializing pool with {} workers ...", args.workers);
+    thread::spawn(move || {
+        // keep the watcher alive as long as this thread
+        let _watcher = watcher;
 
-    let pool = ThreadPool::new(args.workers);
+        for event in rx {
+            match event {
+                DebouncedEvent::Create(_)
+                | DebouncedEvent::Write(_)
+                | DebouncedEvent::Remove(_)
+                | DebouncedEvent::Rename(_, _)
+                | DebouncedEvent::Rescan => {
+                    if let Err(e) = engine.reload() {
+                        log::error!("could not reload rules, keeping the current ones: {}", e);
+                    }
+                }
+                DebouncedEvent::Error(error, maybe_path) => {
+                    log::error!("rules watch error for {:?}: {:?}", maybe_path, error);
+                }
+                _ => {}
+            }
+        }
+    });
 
-    log::info!("running ...");
+    Ok(())
+}
+
+/// Monitors the root folders, scanning files as they are created or modified and passing
+/// their results to the callback, while reloading the rules whenever they change. Only
+/// returns if monitoring could not be started.
+pub fn monitor<P, F>(
+    engine: Arc<Engine>,
+    roots: &[P],
+    options: &Options,
+    reporter: Arc<dyn Reporter>,
+    callback: F,
+) -> Result<(), Error>
+where
+    P: AsRef<Path>,
+    F: Fn(&Detection) + Send + Sync + 'static,
+{
+    let filter = Filter::from_options(options)?;
+    let callback: Callback = Arc::new(callback);
+
+    let (tx, rx) = channel();
+    let mut watcher = watcher(tx, Duration::ZERO).map_err(|e| e.to_string())?;
+
+    // create a recursive filesystem monitor for each root path
+    for root in unique_roots(roots) {
+        log::info!("initializing filesystem monitor for {:?} ...", &root);
+
+        watcher
+            .watch(&root, RecursiveMode::Recursive)
+            .map_err(|e| format!("can't watch {:?}: {}", &root, e))?;
+    }
+
+    log::info!("initializing pool with {} workers ...", options.workers);
 
-    let engine = Arc::new(engine);
+    let pool = ThreadPool::new(options.workers);
+
+    let pipeline = Arc::new(Pipeline::new(options, engine.clone(), reporter)?);
+
+    // filesystem events are coalesced and bounded before reaching the pool
+    let queue = Arc::new(WorkQueue::new(options.queue_size, options.workers));
+    dispatch(
+        queue.clone(),
+        pool.clone(),
+        pipeline.clone(),
+        callback.clone(),
+    );
+    log_queue_stats(queue.clone());
+
+    watch_rules(engine.rules_path(), engine.clone())?;
+
+    #[cfg(target_os = "linux")]
+    if let Some(interval) = options.exec_monitor {
+        crate::exec_monitor::start(
+            engine.clone(),
+            pipeline.clone(),
+            pool.clone(),
+            interval,
+            callback.clone(),
+        );
+    }
+    #[cfg(not(target_os = "linux"))]
+    if options.exec_monitor.is_some() {
+        return Err("exec monitoring is only supported on Linux".to_string());
+    }
+
+    log::info!("running ...");
 
     // receive filesystem events
     loop {
@@ -36,24 +183,14 @@ pub(crate) fn start(args: Arguments, engine: Engine) -> Result<(), String> {
                 | DebouncedEvent::NoticeWrite(path)
                 | DebouncedEvent::Write(path)
                 | DebouncedEvent::Rename(_, path) => {
-                    // if it's a file and it exists
-                    if path.is_file() && path.exists() {
-                        // create a reference to the engine
-                        let an_engine = engine.clone();
-                        // submit scan job to the threads pool
-                        pool.execute(move || {
-                            // perform the scanning
-                            let res = an_engine.scan(&path);
-                            if let Some(error) = res.error {
-                                log::debug!("{:?}", error)
-                            } else if res.detected {
-                                log::warn!(
-                                    "!!! MALWARE DETECTION: '{:?}' detected as '{:?}'",
-                                    &path,
-                                    res.tags.join(", ")
-                                );
-                            }
-                        });
+                    // if it's a file that exists and is not excluded
+                    if filter.is_excluded_tree(&path) {
+                        log::trace!("ignoring event for excluded {:?}", path);
+                    } else if !filter.has_allowed_ext(&path) {
+                        log::trace!("ignoring event for {:?}, extension not allowed", path);
+                    } else if path.is_file() && path.exists() {
+                        // queue the path unless it's already waiting to be scanned
+                        queue.push(path);
                     }
                 }
 
@@ -0,0 +1,65 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use md5::Md5;
+use serde::Serialize;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+// size of the chunks files are read in while hashing
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Hashes of a file content.
+#[derive(Clone, Debug, Serialize)]
+pub struct Hashes {
+    pub md5: String,
+    pub sha1: String,
+    pub sha256: String,
+}
+
+impl Hashes {
+    /// Computes all hashes in a single pass without loading the file in memory.
+    pub fn of_file(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let mut buffer = vec![0u8; CHUNK_SIZE];
+        let mut md5 = Md5::new();
+        let mut sha1 = Sha1::new();
+        let mut sha256 = Sha256::new();
+
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            md5.update(&buffer[..read]);
+            sha1.update(&buffer[..read]);
+            sha256.update(&buffer[..read]);
+        }
+
+        Ok(Hashes {
+            md5: format!("{:x}", md5.finalize()),
+            sha1: format!("{:x}", sha1.finalize()),
+            sha256: format!("{:x}", sha256.finalize()),
+        })
+    }
+
+    /// Computes all hashes of data already in memory.
+    pub fn of_bytes(data: &[u8]) -> Self {
+        Hashes {
+            md5: format!("{:x}", Md5::digest(data)),
+            sha1: format!("{:x}", Sha1::digest(data)),
+            sha256: format!("{:x}", Sha256::digest(data)),
+        }
+    }
+}
+
+/// Computes the SHA-256 of a file without loading it in memory.
+pub(crate) fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut reader = io::BufReader::new(File::open(path)?);
+    let mut hasher = Sha256::new();
+
+    io::copy(&mut reader, &mut hasher)?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
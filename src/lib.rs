@@ -0,0 +1,44 @@
+//! Minimalistic cross-platform filesystem monitor and malware scanner using YARA rules.
+//!
+//! An [`Engine`] compiles the rules described by a [`Configuration`], then [`scan`] walks
+//! folders returning the [`Detection`] of each file as it's scanned while [`monitor`] scans
+//! files as they are created or modified, passing the results to a callback. Detections,
+//! errors and action outcomes are also sent to a [`Reporter`].
+mod actions;
+mod archive;
+mod cache;
+#[cfg(unix)]
+mod clamd;
+mod engine;
+#[cfg(target_os = "linux")]
+mod exec_monitor;
+mod filter;
+mod fs_monitor;
+mod fs_scan;
+mod hashes;
+mod options;
+mod pipeline;
+#[cfg(target_os = "linux")]
+mod proc_scan;
+pub mod quarantine;
+mod queue;
+pub mod report;
+mod serve;
+
+pub use actions::{parse_action, Action, Outcome, Selector};
+pub use archive::Limits;
+#[cfg(unix)]
+pub use clamd::serve_clamd;
+pub use engine::{
+    Configuration, Detection, Engine, Error, MetaValue, NamespaceMode, ProcessInfo, RuleMatch,
+    StringMatch, Tag, BUILTIN_EXTERNALS, DEFAULT_RULES_EXTENSIONS,
+};
+pub use fs_monitor::monitor;
+pub use fs_scan::scan;
+pub use hashes::Hashes;
+pub use options::{Options, DEFAULT_QUARANTINE_DIR};
+pub use pipeline::Scan;
+#[cfg(target_os = "linux")]
+pub use proc_scan::scan_processes;
+pub use report::{Reporter, Summary};
+pub use serve::serve;
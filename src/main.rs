@@ -1,51 +1,272 @@
-use clap::Parser;
-
-mod engine;
-mod fs_monitor;
-mod fs_scan;
-
-#[derive(Parser, Default, Debug)]
-#[clap(
-    about = "Minimalistic cross-platform filesystem monitor and malware scanner using YARA rules."
-)]
-struct Arguments {
-    /// Root path of the filesystem to monitor.
-    #[clap(long, default_value = "/")]
-    root: String,
-    /// Path of YARA rules to use.
-    #[clap(long)]
-    rules: String,
-    /// Number of worker threads used for scanning.
-    #[clap(long, default_value_t = 32)]
-    workers: usize,
-    /// Scan timeout in seconds.
-    #[clap(long, default_value_t = 30)]
-    scan_timeout: i32,
-    /// Perform a scan of every file in the specified root folder and exit.
-    #[clap(long, takes_value = false)]
-    scan: bool,
-    /// Only scan files with the specified extension if --scan is used, can be passed multiple times.
-    #[clap(long)]
-    ext: Vec<String>,
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches};
+
+mod args;
+mod commands;
+mod config;
+
+use args::{Arguments, Cli, Command, ConfigCommand, QuarantineCommand, RulesCommand, ScanCommand};
+use sauron::report;
+use sauron::{Configuration, Engine, Summary};
+
+// what to run
+enum Task {
+    Scan(Vec<String>),
+    ScanStdin(String),
+    ScanProcesses,
+    Monitor,
+    Serve,
+    Daemon,
+    RulesCompile(String),
+    RulesList,
+    RulesTest(Vec<String>),
+    QuarantineList,
+    QuarantineRestore(String),
+    ConfigCheck,
 }
 
-fn main() -> Result<(), String> {
-    pretty_env_logger::init();
+impl Task {
+    fn of(cli: &Cli) -> Self {
+        match &cli.command {
+            Some(Command::Scan(ScanCommand {
+                paths,
+                label,
+                processes,
+                ..
+            })) => {
+                if *processes {
+                    Task::ScanProcesses
+                } else if paths == &[STDIN_PATH] {
+                    Task::ScanStdin(label.clone())
+                } else {
+                    Task::Scan(paths.clone())
+                }
+            }
+            Some(Command::Monitor(_)) => Task::Monitor,
+            Some(Command::Serve(_)) => Task::Serve,
+            Some(Command::Daemon(_)) => Task::Daemon,
+            Some(Command::Rules { command }) => match command {
+                RulesCommand::Compile { path, .. } => Task::RulesCompile(path.clone()),
+                RulesCommand::List { .. } => Task::RulesList,
+                RulesCommand::Test { paths, .. } => Task::RulesTest(paths.clone()),
+            },
+            Some(Command::Quarantine { command }) => match command {
+                QuarantineCommand::List { .. } => Task::QuarantineList,
+                QuarantineCommand::Restore { id, .. } => Task::QuarantineRestore(id.clone()),
+            },
+            Some(Command::Config {
+                command: ConfigCommand::Check,
+            }) => Task::ConfigCheck,
+            // flat arguments, kept for backwards compatibility
+            None => {
+                let legacy = &cli.legacy;
+                if let Some(id) = &legacy.restore {
+                    log::warn!("--restore is deprecated, use 'sauron quarantine restore'");
+                    Task::QuarantineRestore(id.clone())
+                } else if legacy.processes {
+                    log::warn!("--processes is deprecated, use 'sauron scan --processes'");
+                    Task::ScanProcesses
+                } else if legacy.scan {
+                    log::warn!("--scan is deprecated, use 'sauron scan'");
+                    Task::Scan(vec![])
+                } else {
+                    log::warn!("running without a command is deprecated, use 'sauron monitor'");
+                    Task::Monitor
+                }
+            }
+        }
+    }
+}
+
+// the arguments of the command being run
+fn command_matches(matches: &ArgMatches) -> &ArgMatches {
+    match matches.subcommand() {
+        Some((_, sub_matches)) => command_matches(sub_matches),
+        None => matches,
+    }
+}
+
+fn engine_configuration(args: &Arguments) -> Result<Configuration, String> {
+    let rules = &args.rules;
+
+    Ok(Configuration {
+        data_path: rules
+            .rules
+            .clone()
+            .ok_or("no rules path, use --rules or set 'rules' in the configuration file")?,
+        timeout: rules.scan_timeout,
+        extensions: rules.rules_ext.clone(),
+        namespace: (&rules.namespace).into(),
+        compiled_rules: rules.compiled_rules.clone(),
+        save_compiled: rules.save_compiled.clone(),
+        strict: rules.strict_rules,
+        min_file_size: args.files.min_file_size,
+        max_file_size: args.files.max_file_size,
+        externals: rules.define.clone(),
+    })
+}
+
+// process exit codes, besides 0 for a clean scan or a successful command
+const EXIT_DETECTED: u8 = 1;
+const EXIT_ERRORS: u8 = 2;
+const EXIT_FATAL: u8 = 3;
+
+// path of the standard input for the scan command
+const STDIN_PATH: &str = "-";
+
+// runs the command, returning the summary of the scan if it exits after scanning
+fn run(matches: &ArgMatches) -> Result<Option<Summary>, String> {
+    let cli = Cli::from_arg_matches(matches).map_err(|e| e.to_string())?;
+
+    // the deprecated flat arguments can't be mixed with commands
+    if cli.command.is_some() {
+        if let Some(arg) = Cli::command().get_arguments().find(|arg| {
+            let id = arg.get_id();
+            id != "config"
+                && matches.try_contains_id(id).unwrap_or(false)
+                && matches.value_source(id) == Some(ValueSource::CommandLine)
+        }) {
+            return Err(format!(
+                "--{} must be passed after the command",
+                arg.get_long().unwrap_or_else(|| arg.get_id())
+            ));
+        }
+    }
 
-    let args = Arguments::parse();
+    let mut args = cli.arguments();
+
+    // command line arguments override the configuration file
+    config::load(cli.config.as_ref(), &mut args, command_matches(matches))?;
+
+    let task = Task::of(&cli);
+
+    match task {
+        Task::ConfigCheck => {
+            // validate the configuration and print it
+            config::check(&args)?;
+            let effective = toml::to_string_pretty(&config::FileConfig::from_args(&args))
+                .map_err(|e| format!("can't serialize configuration: {}", e))?;
+            print!("{}", effective);
+            return Ok(None);
+        }
+        Task::QuarantineList => return commands::quarantine_list(&args).map(|_| None),
+        Task::QuarantineRestore(id) => {
+            return commands::quarantine_restore(&args, &id).map(|_| None)
+        }
+        _ => {}
+    }
+
+    match &task {
+        Task::RulesCompile(path) => args.rules.save_compiled = Some(path.clone()),
+        Task::Scan(paths) if paths.iter().any(|path| path == STDIN_PATH) => {
+            return Err(format!(
+                "{} can't be scanned along with other paths",
+                STDIN_PATH
+            ))
+        }
+        // paths passed to the scan command replace the roots of the configuration file
+        Task::Scan(paths) if !paths.is_empty() => args.files.root = paths.clone(),
+        _ => {}
+    }
 
     // initialize the scan engine
-    let config = engine::Configuration {
-        data_path: args.rules.clone(),
-        timeout: args.scan_timeout,
-    };
-    let engine = engine::Engine::new(config)?;
+    let engine = Arc::new(Engine::new(engine_configuration(&args)?)?);
+
+    match task {
+        Task::RulesCompile(path) => {
+            log::info!("compiled rules saved to {}", path);
+            return Ok(None);
+        }
+        Task::RulesList => return commands::rules_list(&engine).map(|_| None),
+        _ => {}
+    }
 
-    if args.scan {
+    // initialize the results reporter
+    let reporter: Arc<dyn report::Reporter> = Arc::from(report::create(
+        &(&args.report.output).into(),
+        args.report.output_file.as_ref(),
+    )?);
+
+    let summary = match task {
+        // scan files with the rules only
+        Task::RulesTest(paths) => commands::rules_test(&engine, &paths, reporter)?,
+        // scan the memory of the running processes and exit
+        #[cfg(target_os = "linux")]
+        Task::ScanProcesses => commands::wait(
+            sauron::scan_processes(engine, &args.options(), reporter.clone())?,
+            &*reporter,
+        ),
+        #[cfg(not(target_os = "linux"))]
+        Task::ScanProcesses => {
+            return Err("process scanning is only supported on Linux".to_string())
+        }
+        // scan the data piped to us and exit
+        Task::ScanStdin(label) => commands::scan_stdin(&engine, &args, &label, reporter)?,
         // perform a scan of the root folder and exit
-        fs_scan::start(args, engine)
-    } else {
-        // monitor the filesystem
-        fs_monitor::start(args, engine)
+        Task::Scan(_) => commands::wait(
+            sauron::scan(engine, &args.files.root, &args.options(), reporter.clone())?,
+            &*reporter,
+        ),
+        // scan on demand
+        Task::Serve => {
+            return sauron::serve(
+                engine,
+                &args.serve.listen,
+                args.pool.workers,
+                args.serve.max_body_size,
+                args.serve.allow_path_scan,
+                args.serve.allow_remote,
+                reporter,
+            )
+            .map(|_| None)
+        }
+        // scan on demand for clamd clients
+        #[cfg(unix)]
+        Task::Daemon => {
+            return sauron::serve_clamd(
+                engine,
+                &args.daemon.socket,
+                args.pool.workers,
+                args.daemon.max_stream_size,
+                reporter,
+            )
+            .map(|_| None)
+        }
+        #[cfg(not(unix))]
+        Task::Daemon => return Err("the clamd daemon is only supported on Unix".to_string()),
+        // monitor the filesystem, results are reported as they come
+        _ => {
+            return sauron::monitor(engine, &args.files.root, &args.options(), reporter, |_| {})
+                .map(|_| None)
+        }
+    };
+
+    Ok(Some(summary))
+}
+
+fn main() -> ExitCode {
+    pretty_env_logger::init();
+
+    let matches = match Cli::command().try_get_matches() {
+        Ok(matches) => matches,
+        // --help and --version
+        Err(e) if !e.use_stderr() => e.exit(),
+        Err(e) => {
+            let _ = e.print();
+            return ExitCode::from(EXIT_FATAL);
+        }
+    };
+
+    match run(&matches) {
+        Ok(Some(summary)) if summary.detected > 0 => ExitCode::from(EXIT_DETECTED),
+        Ok(Some(summary)) if summary.errors > 0 => ExitCode::from(EXIT_ERRORS),
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            log::error!("{}", e);
+            ExitCode::from(EXIT_FATAL)
+        }
     }
 }
@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use crate::actions::{Action, Selector};
+use crate::archive::Limits;
+use crate::engine::Error;
+use crate::filter::Filter;
+
+/// Folder where detected files are moved by the quarantine action, unless configured otherwise.
+pub const DEFAULT_QUARANTINE_DIR: &str = "/var/lib/sauron/quarantine";
+
+/// Settings of the scan and monitor APIs, the rules being configured on the engine.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Number of worker threads used for scanning.
+    pub workers: usize,
+    /// Only scan files with these extensions, all if empty.
+    pub extensions: Vec<String>,
+    /// Exclude paths matching these globs, or regular expressions if prefixed with 're:'.
+    pub exclude: Vec<String>,
+    /// Also exclude /proc, /sys and /dev.
+    pub default_excludes: bool,
+    /// Never scan these exact paths, like the files written by the caller.
+    pub skip_paths: Vec<String>,
+    /// Scan the files inside zip, tar, gzip and bzip2 archives within these limits.
+    pub archives: Option<Limits>,
+    /// Remember files found clean in this file and skip them until they or the rules change.
+    pub cache: Option<String>,
+    /// Compute the hashes of every scanned file, not only of detected ones, and report clean files.
+    pub hash_all: bool,
+    /// Actions to run on the detections matching their selector.
+    pub actions: Vec<(Selector, Action)>,
+    /// Timeout of exec actions.
+    pub action_timeout: Duration,
+    /// Folder of the quarantined files.
+    pub quarantine_dir: String,
+    /// Only scan the processes with these pids, all if empty.
+    pub pids: Vec<u32>,
+    /// Only scan the processes of these user names or ids, all if empty.
+    pub users: Vec<String>,
+    /// Only scan the processes with these names, all if empty.
+    pub process_names: Vec<String>,
+    /// When monitoring, maximum number of files waiting to be scanned, further events are dropped.
+    pub queue_size: usize,
+    /// When monitoring, also scan new processes checking for them at this interval (Linux only).
+    pub exec_monitor: Option<Duration>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            workers: 32,
+            extensions: vec![],
+            exclude: vec![],
+            default_excludes: true,
+            skip_paths: vec![],
+            archives: None,
+            cache: None,
+            hash_all: false,
+            actions: vec![],
+            action_timeout: Duration::from_secs(30),
+            quarantine_dir: DEFAULT_QUARANTINE_DIR.to_string(),
+            pids: vec![],
+            users: vec![],
+            process_names: vec![],
+            queue_size: 10000,
+            exec_monitor: None,
+        }
+    }
+}
+
+impl Options {
+    /// Validates the exclusions without running anything.
+    pub fn check(&self) -> Result<(), Error> {
+        Filter::new(&self.exclude, &self.extensions)?;
+
+        Ok(())
+    }
+}
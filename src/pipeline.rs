@@ -0,0 +1,356 @@
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+use sha2::{Digest, Sha256};
+use threadpool::ThreadPool;
+
+use crate::actions::Actions;
+use crate::archive::{self, Limits};
+use crate::cache::{Lookup, ScanCache};
+use crate::engine::{Detection, Engine, ProcessInfo};
+use crate::hashes::Hashes;
+use crate::options::Options;
+use crate::report::{Reporter, Summary};
+
+/// What happens to each file submitted for scanning, shared by the scan and monitor modes.
+pub(crate) struct Pipeline {
+    engine: Arc<Engine>,
+    reporter: Arc<dyn Reporter>,
+    actions: Actions,
+    cache: Option<ScanCache>,
+    // fingerprint of the settings deciding what is scanned, clean results only hold for them
+    cache_settings: String,
+    hash_all: bool,
+    archive_limits: Option<Limits>,
+    // errors reported besides the ones of the returned detections, like those of archive members
+    errors: AtomicU32,
+}
+
+impl Pipeline {
+    pub fn new(
+        options: &Options,
+        engine: Arc<Engine>,
+        reporter: Arc<dyn Reporter>,
+    ) -> Result<Self, String> {
+        let actions = Actions::from_options(options)?;
+        let cache = match &options.cache {
+            Some(path) => Some(ScanCache::load(path)?),
+            None => None,
+        };
+
+        let cache_settings = format!(
+            "{:x}",
+            Sha256::digest(format!(
+                "ext={:?};size={:?};archives={:?}",
+                &options.extensions,
+                engine.size_limits(),
+                &options.archives
+            ))
+        );
+
+        Ok(Pipeline {
+            engine,
+            reporter,
+            actions,
+            cache,
+            cache_settings,
+            hash_all: options.hash_all,
+            archive_limits: options.archives.clone(),
+            errors: AtomicU32::new(0),
+        })
+    }
+
+    /// Scans a file, reports the result and runs the actions for detections.
+    /// Returns None if the file has been skipped because it's known to be clean.
+    pub fn process(&self, path: &Path) -> Option<Detection> {
+        let fingerprint = format!("{}-{}", self.engine.fingerprint(), &self.cache_settings);
+        // files skipped because of their size are neither looked up nor recorded as clean
+        let scannable = fs::metadata(path)
+            .map(|metadata| self.engine.is_scannable(path, metadata.len()))
+            .unwrap_or(true);
+
+        let pending = match &self.cache {
+            Some(cache) if scannable => match cache.lookup(path, &fingerprint) {
+                Lookup::Clean => {
+                    log::trace!("{:?} is known to be clean, skipping", path);
+                    return None;
+                }
+                Lookup::Miss(pending) => Some(pending),
+            },
+            _ => None,
+        };
+
+        // perform the scanning
+        let mut res = self.engine.scan(&path.to_path_buf());
+
+        // scan the files inside archives, unless the archive itself was skipped because of its size
+        if let (true, None, Some(limits)) = (scannable, &res.error, &self.archive_limits) {
+            let members = archive::scan(&self.engine, path, limits);
+            for (member_path, error) in &members.errors {
+                self.error(member_path, error);
+            }
+            for member in members.detections {
+                res.detected = true;
+                for tag in &member.tags {
+                    if !res.tags.contains(tag) {
+                        res.tags.push(tag.clone());
+                    }
+                }
+                res.members.push(member);
+            }
+        }
+
+        if res.error.is_none() && (res.detected || (self.hash_all && scannable)) {
+            // reuse the hashes computed by the cache lookup instead of reading the file again
+            let hashes = match pending.as_ref().and_then(|pending| pending.hashes()) {
+                Some(hashes) => Ok(hashes.clone()),
+                None => Hashes::of_file(path),
+            };
+            match hashes {
+                Ok(hashes) => {
+                    log::debug!("{:?} sha256={}", path, &hashes.sha256);
+                    res.hashes = Some(hashes);
+                }
+                Err(e) => log::warn!("can't hash {:?}: {:?}", path, e),
+            }
+        }
+
+        if let Some(error) = &res.error {
+            self.reporter.error(path, error);
+        } else if res.detected {
+            self.reporter.detection(&res);
+            self.actions.run(&res, &*self.reporter);
+        } else {
+            if self.hash_all && scannable {
+                self.reporter.scanned(&res);
+            }
+            if let (Some(cache), Some(pending)) = (&self.cache, pending) {
+                cache.insert_clean(pending, &fingerprint);
+            }
+        }
+
+        Some(res)
+    }
+
+    /// Scans the memory of a process, reports the result and runs the actions for detections.
+    pub fn process_memory(&self, process: ProcessInfo) -> Detection {
+        let res = self.engine.scan_process(process);
+
+        if let Some(error) = &res.error {
+            self.reporter.error(&res.path, error);
+        } else if res.detected {
+            self.reporter.detection(&res);
+            self.actions.run(&res, &*self.reporter);
+        }
+
+        res
+    }
+
+    /// Reports an error that is not the result of a scan, like a folder that can't be read or
+    /// a file inside an archive that can't be extracted, counted in the summary of scans.
+    pub fn error(&self, path: &Path, error: &str) {
+        self.reporter.error(path, error);
+        self.errors.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Flushes any pending state, to be called once done scanning.
+    pub fn finish(&self) {
+        if let Some(cache) = &self.cache {
+            cache.save();
+        }
+    }
+}
+
+// counters of the jobs of a scan
+#[derive(Default)]
+struct Counters {
+    scanned: AtomicU32,
+    detected: AtomicU32,
+    errors: AtomicU32,
+}
+
+/// Submits jobs to the workers of a scan.
+pub(crate) struct Jobs {
+    pool: ThreadPool,
+    pipeline: Arc<Pipeline>,
+    counters: Arc<Counters>,
+    results: Sender<Detection>,
+}
+
+impl Jobs {
+    /// Runs a job on the pool, its result is counted and returned by the scan.
+    pub fn submit<F>(&self, job: F)
+    where
+        F: FnOnce(&Pipeline) -> Option<Detection> + Send + 'static,
+    {
+        // create thread-safe references
+        let pipeline = self.pipeline.clone();
+        let counters = self.counters.clone();
+        let results = self.results.clone();
+
+        // submit scan job to the threads pool
+        self.pool.execute(move || {
+            if let Some(res) = job(&pipeline) {
+                if res.error.is_some() {
+                    counters.errors.fetch_add(1, Ordering::SeqCst);
+                } else if res.detected {
+                    counters.detected.fetch_add(1, Ordering::SeqCst);
+                }
+                // nothing to do if the results are not being read anymore
+                let _ = results.send(res);
+            }
+
+            counters.scanned.fetch_add(1, Ordering::SeqCst);
+        });
+    }
+
+    /// Reports an error outside of any job, like a folder that can't be read, counted in the
+    /// summary but not returned by the scan.
+    pub fn error(&self, path: &Path, error: &str) {
+        self.pipeline.error(path, error);
+    }
+}
+
+/// The results of a scan, returned as each file or process is scanned. Files skipped
+/// because they are known to be clean, errors walking folders and errors extracting archives
+/// are only counted in the summary.
+pub struct Scan {
+    results: Receiver<Detection>,
+    counters: Arc<Counters>,
+    pipeline: Arc<Pipeline>,
+    start: Instant,
+}
+
+impl Scan {
+    // run the producer on its own thread, the scan ends once all its jobs are done
+    pub(crate) fn spawn<F>(workers: usize, pipeline: Arc<Pipeline>, producer: F) -> Self
+    where
+        F: FnOnce(&Jobs) + Send + 'static,
+    {
+        log::info!("initializing pool with {} workers ...", workers);
+
+        let (tx, rx) = channel();
+        let counters = Arc::new(Counters::default());
+        let jobs = Jobs {
+            pool: ThreadPool::new(workers),
+            pipeline: pipeline.clone(),
+            counters: counters.clone(),
+            results: tx,
+        };
+
+        thread::spawn(move || {
+            producer(&jobs);
+
+            jobs.pool.join();
+            jobs.pipeline.finish();
+            // dropping the last sender ends the results
+        });
+
+        Scan {
+            results: rx,
+            counters,
+            pipeline,
+            start: Instant::now(),
+        }
+    }
+
+    /// The counters of the scan so far, final once all the results have been read.
+    pub fn summary(&self) -> Summary {
+        Summary {
+            scanned: self.counters.scanned.load(Ordering::SeqCst),
+            detected: self.counters.detected.load(Ordering::SeqCst),
+            errors: self.counters.errors.load(Ordering::SeqCst)
+                + self.pipeline.errors.load(Ordering::SeqCst),
+            elapsed: self.start.elapsed(),
+        }
+    }
+}
+
+impl Iterator for Scan {
+    type Item = Detection;
+
+    fn next(&mut self) -> Option<Detection> {
+        self.results.recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+
+    use crate::actions::Outcome;
+    use crate::engine::{test_engine, test_path};
+
+    // records the paths of the reported clean files and detections
+    #[derive(Default)]
+    struct Recorder {
+        scanned: Mutex<Vec<(PathBuf, bool)>>,
+        detected: Mutex<Vec<PathBuf>>,
+    }
+
+    impl Reporter for Recorder {
+        fn detection(&self, detection: &Detection) {
+            self.detected.lock().unwrap().push(detection.path.clone());
+        }
+        fn scanned(&self, detection: &Detection) {
+            let hashed = detection.hashes.is_some();
+            self.scanned
+                .lock()
+                .unwrap()
+                .push((detection.path.clone(), hashed));
+        }
+        fn error(&self, _path: &Path, _error: &str) {}
+        fn action(&self, _detection: &Detection, _outcome: &Outcome) {}
+        fn summary(&self, _summary: &Summary) {}
+    }
+
+    fn process(hash_all: bool) -> Arc<Recorder> {
+        let folder = test_path("pipeline");
+        fs::create_dir_all(&folder).unwrap();
+        let (clean, evil) = (folder.join("clean"), folder.join("evil"));
+        fs::write(&clean, "hello").unwrap();
+        fs::write(&evil, "xxEVILSTRINGxx").unwrap();
+
+        let recorder = Arc::new(Recorder::default());
+        let options = Options {
+            hash_all,
+            ..Options::default()
+        };
+        let engine = test_engine(r#"rule evil { strings: $a = "EVILSTRING" condition: $a }"#);
+        let pipeline = Pipeline::new(&options, Arc::new(engine), recorder.clone()).unwrap();
+
+        for path in [&clean, &evil] {
+            pipeline.process(path).unwrap();
+        }
+
+        let _ = fs::remove_dir_all(folder);
+
+        recorder
+    }
+
+    #[test]
+    fn reports_clean_files_with_hashes() {
+        let recorder = process(true);
+
+        let scanned = recorder.scanned.lock().unwrap();
+        assert_eq!(scanned.len(), 1);
+        assert!(scanned[0].0.ends_with("clean"));
+        assert!(scanned[0].1);
+        assert_eq!(recorder.detected.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn reports_only_detections_by_default() {
+        let recorder = process(false);
+
+        assert!(recorder.scanned.lock().unwrap().is_empty());
+        assert_eq!(recorder.detected.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,159 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::engine::{Engine, Error, ProcessInfo};
+use crate::options::Options;
+use crate::pipeline::{Pipeline, Scan};
+use crate::report::Reporter;
+
+// selects which processes are scanned, empty lists match any process
+struct ProcessFilter {
+    pids: Vec<u32>,
+    uids: Vec<u32>,
+    names: Vec<String>,
+}
+
+impl ProcessFilter {
+    fn from_options(options: &Options) -> Result<Self, String> {
+        let uids = options
+            .users
+            .iter()
+            .map(|user| user_id(user))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(ProcessFilter {
+            pids: options.pids.clone(),
+            uids,
+            names: options.process_names.clone(),
+        })
+    }
+
+    fn matches(&self, process: &ProcessInfo) -> bool {
+        let exe_name = process
+            .exe
+            .as_ref()
+            .and_then(|exe| exe.file_name())
+            .map(|name| name.to_string_lossy().to_string());
+
+        (self.pids.is_empty() || self.pids.contains(&process.pid))
+            && (self.uids.is_empty() || process.uid.is_some_and(|uid| self.uids.contains(&uid)))
+            && (self.names.is_empty()
+                || self
+                    .names
+                    .iter()
+                    .any(|name| *name == process.name || Some(name) == exe_name.as_ref()))
+    }
+}
+
+// resolve a user name or numeric id to a uid
+fn user_id(user: &str) -> Result<u32, String> {
+    if let Ok(uid) = user.parse::<u32>() {
+        return Ok(uid);
+    }
+
+    let passwd = fs::read_to_string("/etc/passwd")
+        .map_err(|e| format!("can't read /etc/passwd: {:?}", e))?;
+
+    passwd
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.len() > 2 && fields[0] == user)
+        .and_then(|fields| fields[2].parse().ok())
+        .ok_or_else(|| format!("unknown user '{}'", user))
+}
+
+/// Reads the information about a process from /proc, None if it exited in the meantime.
+pub(crate) fn process_info(pid: u32) -> Option<ProcessInfo> {
+    let proc_path = Path::new("/proc").join(pid.to_string());
+
+    let status = fs::read_to_string(proc_path.join("status")).ok()?;
+    let uid = status
+        .lines()
+        .find_map(|line| line.strip_prefix("Uid:"))
+        .and_then(|uids| uids.split_whitespace().next())
+        .and_then(|uid| uid.parse().ok());
+
+    let name = fs::read_to_string(proc_path.join("comm"))
+        .map(|comm| comm.trim_end().to_string())
+        .unwrap_or_default();
+
+    // arguments are separated by NUL bytes
+    let cmdline = fs::read(proc_path.join("cmdline"))
+        .map(|data| {
+            String::from_utf8_lossy(&data)
+                .split('\0')
+                .filter(|arg| !arg.is_empty())
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .unwrap_or_default();
+
+    Some(ProcessInfo {
+        pid,
+        uid,
+        name,
+        exe: fs::read_link(proc_path.join("exe")).ok(),
+        cmdline,
+    })
+}
+
+/// Lists the pids of the processes currently running.
+pub(crate) fn pids() -> Result<Vec<u32>, String> {
+    let entries = fs::read_dir("/proc").map_err(|e| format!("can't read /proc: {:?}", e))?;
+
+    let mut pids: Vec<u32> = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.file_name().to_string_lossy().parse().ok())
+        .collect();
+    pids.sort_unstable();
+
+    Ok(pids)
+}
+
+/// Kernel threads have no user space memory nor executable to scan.
+pub(crate) fn is_kernel_thread(process: &ProcessInfo) -> bool {
+    process.exe.is_none() && process.cmdline.is_empty()
+}
+
+// list the processes currently running, excluding kernel threads
+fn processes() -> Result<Vec<ProcessInfo>, String> {
+    Ok(pids()?
+        .into_iter()
+        .filter_map(process_info)
+        .filter(|p| !is_kernel_thread(p))
+        .collect())
+}
+
+/// Scans the memory of the running processes selected by the options, except our own.
+pub fn scan_processes(
+    engine: Arc<Engine>,
+    options: &Options,
+    reporter: Arc<dyn Reporter>,
+) -> Result<Scan, Error> {
+    let pipeline = Arc::new(Pipeline::new(options, engine, reporter)?);
+    let filter = ProcessFilter::from_options(options)?;
+    let processes = processes()?;
+
+    // our own memory contains the rules, which would match themselves
+    let own_pid = std::process::id();
+
+    Ok(Scan::spawn(options.workers, pipeline, move |jobs| {
+        log::info!("scanning processes memory ...");
+
+        for process in processes {
+            if process.pid == own_pid || !filter.matches(&process) {
+                continue;
+            }
+
+            log::debug!(
+                "scanning process {} ({}) {:?}",
+                process.pid,
+                &process.name,
+                &process.cmdline
+            );
+
+            jobs.submit(move |pipeline| Some(pipeline.process_memory(process)));
+        }
+    }))
+}
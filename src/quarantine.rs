@@ -0,0 +1,340 @@
+use std::fs::{self, File, FileTimes};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use chrono::{SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::engine::Detection;
+use crate::hashes::sha256_file;
+
+// quarantined files are readable by their owner only
+#[cfg(unix)]
+const QUARANTINE_MODE: u32 = 0o400;
+
+/// Metadata stored alongside each quarantined file.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Record {
+    /// Name of the quarantined file: its SHA-256, followed by -N for further copies of the
+    /// same content quarantined from other paths.
+    #[serde(skip)]
+    pub id: String,
+    pub sha256: String,
+    pub original_path: PathBuf,
+    pub size: u64,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub mode: Option<u32>,
+    pub accessed: Option<SystemTime>,
+    pub modified: Option<SystemTime>,
+    pub quarantined_at: String,
+    pub rules: Vec<String>,
+}
+
+/// Moves detected files into a quarantine folder and restores them.
+pub struct Quarantine {
+    path: PathBuf,
+    // serializes operations on the quarantine folder across worker threads
+    lock: Mutex<()>,
+}
+
+impl Quarantine {
+    pub fn new(path: &str) -> Result<Self, String> {
+        let path = PathBuf::from(path);
+
+        fs::create_dir_all(&path)
+            .map_err(|e| format!("can't create quarantine folder {:?}: {:?}", &path, e))?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o700))
+                .map_err(|e| format!("can't set permissions of {:?}: {:?}", &path, e))?;
+        }
+
+        Ok(Quarantine {
+            path,
+            lock: Mutex::new(()),
+        })
+    }
+
+    fn data_path(&self, id: &str) -> PathBuf {
+        self.path.join(id)
+    }
+
+    fn record_path(&self, id: &str) -> PathBuf {
+        self.path.join(format!("{}.json", id))
+    }
+
+    // the first free id for this content, each copy keeps its own record
+    fn free_id(&self, sha256: &str) -> String {
+        let is_free = |id: &str| !self.data_path(id).exists() && !self.record_path(id).exists();
+
+        if is_free(sha256) {
+            return sha256.to_string();
+        }
+
+        (1..)
+            .map(|n| format!("{}-{}", sha256, n))
+            .find(|id| is_free(id))
+            .unwrap()
+    }
+
+    /// Moves the detected file into the quarantine folder.
+    pub fn quarantine(&self, detection: &Detection) -> Result<Record, String> {
+        let path = &detection.path;
+        let sha256 = match &detection.hashes {
+            Some(hashes) => hashes.sha256.clone(),
+            None => sha256_file(path).map_err(|e| format!("can't hash {:?}: {:?}", path, e))?,
+        };
+
+        let _guard = self.lock.lock().unwrap();
+
+        let metadata = fs::metadata(path)
+            .map_err(|e| format!("can't get metadata for {:?}: {:?}", path, e))?;
+
+        let id = self.free_id(&sha256);
+        let data_path = self.data_path(&id);
+
+        let record = Record {
+            id,
+            sha256,
+            original_path: fs::canonicalize(path).unwrap_or_else(|_| path.clone()),
+            size: metadata.len(),
+            uid: owner(&metadata).map(|(uid, _)| uid),
+            gid: owner(&metadata).map(|(_, gid)| gid),
+            mode: mode(&metadata),
+            accessed: metadata.accessed().ok(),
+            modified: metadata.modified().ok(),
+            quarantined_at: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            rules: detection
+                .all_matches()
+                .into_iter()
+                .map(|m| format!("{}:{}", &m.namespace, &m.identifier))
+                .collect(),
+        };
+
+        move_file(path, &data_path)?;
+
+        // make sure the quarantined file can't be executed
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            fs::set_permissions(&data_path, fs::Permissions::from_mode(QUARANTINE_MODE))
+                .map_err(|e| format!("can't set permissions of {:?}: {:?}", &data_path, e))?;
+        }
+
+        self.write_record(&record)?;
+
+        Ok(record)
+    }
+
+    /// Moves a quarantined file back to its original path.
+    pub fn restore(&self, id: &str) -> Result<Record, String> {
+        let (sha256, copy) = id.split_once('-').unwrap_or((id, "1"));
+        if sha256.len() != 64
+            || !sha256.chars().all(|c| c.is_ascii_hexdigit())
+            || copy.is_empty()
+            || !copy.chars().all(|c| c.is_ascii_digit())
+        {
+            return Err(format!("'{}' is not a valid quarantine id", id));
+        }
+
+        let _guard = self.lock.lock().unwrap();
+
+        let record = self.read_record(id)?;
+        let path = &record.original_path;
+
+        if path.exists() {
+            return Err(format!("can't restore {}: {:?} already exists", id, path));
+        }
+
+        move_file(&self.data_path(id), path)?;
+
+        // restore timestamps first, the original permissions might not allow to open the file
+        let mut times = FileTimes::new();
+        if let Some(accessed) = record.accessed {
+            times = times.set_accessed(accessed);
+        }
+        if let Some(modified) = record.modified {
+            times = times.set_modified(modified);
+        }
+        if let Err(e) = File::options()
+            .write(true)
+            .open(path)
+            .and_then(|f| f.set_times(times))
+        {
+            log::warn!("can't restore timestamps of {:?}: {:?}", path, e);
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            if let Some(mode) = record.mode {
+                fs::set_permissions(path, fs::Permissions::from_mode(mode))
+                    .map_err(|e| format!("can't set permissions of {:?}: {:?}", path, e))?;
+            }
+            // changing the owner requires privileges, don't fail if we can't
+            if let Err(e) = std::os::unix::fs::chown(path, record.uid, record.gid) {
+                log::warn!("can't restore owner of {:?}: {:?}", path, e);
+            }
+        }
+
+        fs::remove_file(self.record_path(id))
+            .map_err(|e| format!("can't remove record for {}: {:?}", id, e))?;
+
+        Ok(record)
+    }
+
+    /// Returns the records of the quarantined files, oldest first.
+    pub fn list(&self) -> Result<Vec<Record>, String> {
+        let entries = fs::read_dir(&self.path)
+            .map_err(|e| format!("can't read quarantine folder {:?}: {:?}", &self.path, e))?;
+
+        let mut records = vec![];
+        for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+            if path.extension().is_some_and(|ext| ext == "json") {
+                let id = path.file_stem().unwrap_or_default().to_string_lossy();
+                match self.read_record(&id) {
+                    Ok(record) => records.push(record),
+                    Err(e) => log::warn!("{}", e),
+                }
+            }
+        }
+
+        records.sort_by(|a, b| a.quarantined_at.cmp(&b.quarantined_at));
+
+        Ok(records)
+    }
+
+    fn read_record(&self, id: &str) -> Result<Record, String> {
+        let record_path = self.record_path(id);
+        let data = fs::read_to_string(&record_path)
+            .map_err(|e| format!("can't read {:?}: {:?}", &record_path, e))?;
+
+        let mut record: Record = serde_json::from_str(&data)
+            .map_err(|e| format!("can't parse {:?}: {:?}", &record_path, e))?;
+        record.id = id.to_string();
+
+        Ok(record)
+    }
+
+    fn write_record(&self, record: &Record) -> Result<(), String> {
+        let record_path = self.record_path(&record.id);
+        let data = serde_json::to_string_pretty(record).map_err(|e| e.to_string())?;
+
+        // write to a temporary file first so that records are never partially written
+        let tmp_path = record_path.with_extension("json.tmp");
+        fs::write(&tmp_path, data)
+            .and_then(|_| fs::rename(&tmp_path, &record_path))
+            .map_err(|e| format!("can't write {:?}: {:?}", &record_path, e))
+    }
+}
+
+// rename the file or, if source and destination are on different filesystems, copy and delete it
+fn move_file(from: &Path, to: &Path) -> Result<(), String> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    fs::copy(from, to)
+        .and_then(|_| fs::remove_file(from))
+        .map(|_| ())
+        .map_err(|e| format!("can't move {:?} to {:?}: {:?}", from, to, e))
+}
+
+#[cfg(unix)]
+fn owner(metadata: &fs::Metadata) -> Option<(u32, u32)> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some((metadata.uid(), metadata.gid()))
+}
+
+#[cfg(not(unix))]
+fn owner(_metadata: &fs::Metadata) -> Option<(u32, u32)> {
+    None
+}
+
+#[cfg(unix)]
+fn mode(metadata: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+
+    Some(metadata.permissions().mode())
+}
+
+#[cfg(not(unix))]
+fn mode(_metadata: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::engine::{test_engine, test_path};
+
+    #[test]
+    fn keeps_a_record_for_each_copy() {
+        let engine = test_engine(r#"rule evil { strings: $a = "EVILSTRING" condition: $a }"#);
+        let folder = test_path("quarantine");
+        let quarantine = Quarantine::new(&folder.join("q").to_string_lossy()).unwrap();
+
+        let paths: Vec<PathBuf> = ["one", "two"].iter().map(|n| folder.join(n)).collect();
+        for path in &paths {
+            fs::write(path, "xxEVILSTRINGxx").unwrap();
+        }
+
+        let records: Vec<Record> = paths
+            .iter()
+            .map(|path| quarantine.quarantine(&engine.scan(path)).unwrap())
+            .collect();
+
+        assert_eq!(records[0].sha256, records[1].sha256);
+        assert_eq!(records[0].id, records[0].sha256);
+        assert_eq!(records[1].id, format!("{}-1", records[1].sha256));
+        assert!(paths.iter().all(|path| !path.exists()));
+
+        let mut listed: Vec<(String, PathBuf)> = quarantine
+            .list()
+            .unwrap()
+            .into_iter()
+            .map(|record| (record.id, record.original_path))
+            .collect();
+        listed.sort();
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[0].1.file_name().unwrap(), "one");
+        assert_eq!(listed[1].1.file_name().unwrap(), "two");
+
+        for record in &records {
+            quarantine.restore(&record.id).unwrap();
+        }
+        assert!(paths.iter().all(|path| path.exists()));
+        assert!(quarantine.list().unwrap().is_empty());
+
+        let _ = fs::remove_dir_all(folder);
+    }
+
+    #[test]
+    fn rejects_invalid_ids() {
+        let folder = test_path("quarantine");
+        let quarantine = Quarantine::new(&folder.to_string_lossy()).unwrap();
+        let sha256 = "a".repeat(64);
+
+        for id in [
+            "../etc/passwd".to_string(),
+            "a".repeat(63),
+            format!("{}-", sha256),
+            format!("{}-x", sha256),
+            format!("{}-1/../x", sha256),
+        ] {
+            let error = quarantine.restore(&id).unwrap_err();
+            assert!(error.contains("not a valid quarantine id"), "{}", error);
+        }
+
+        let _ = fs::remove_dir_all(folder);
+    }
+}
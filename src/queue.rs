@@ -0,0 +1,192 @@
+use std::collections::{HashSet, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Counters of a work queue.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Stats {
+    /// Paths waiting to be scanned.
+    pub depth: usize,
+    /// Paths being scanned.
+    pub in_flight: usize,
+    /// Events for paths that were already waiting to be scanned.
+    pub coalesced: u64,
+    /// Events dropped because the queue was full.
+    pub dropped: u64,
+}
+
+#[derive(Default)]
+struct State {
+    order: VecDeque<PathBuf>,
+    queued: HashSet<PathBuf>,
+    in_flight: usize,
+    coalesced: u64,
+    dropped: u64,
+}
+
+/// A path taken from the queue, its scan slot is released when dropped.
+pub(crate) struct Work {
+    pub path: PathBuf,
+    queue: Arc<WorkQueue>,
+}
+
+impl Drop for Work {
+    fn drop(&mut self) {
+        self.queue.done();
+    }
+}
+
+/// Bounded queue of the paths to scan, where a path is queued once however many events it
+/// gets until it's scanned, and at most a given number of paths are scanned at a time.
+pub(crate) struct WorkQueue {
+    state: Mutex<State>,
+    changed: Condvar,
+    capacity: usize,
+    max_in_flight: usize,
+}
+
+impl WorkQueue {
+    pub fn new(capacity: usize, max_in_flight: usize) -> Self {
+        WorkQueue {
+            state: Mutex::new(State::default()),
+            changed: Condvar::new(),
+            capacity: capacity.max(1),
+            max_in_flight: max_in_flight.max(1),
+        }
+    }
+
+    /// Queues a path unless it's already waiting, returns false if it was dropped because the
+    /// queue is full.
+    pub fn push(&self, path: PathBuf) -> bool {
+        let mut state = self.state.lock().unwrap();
+
+        if state.queued.contains(&path) {
+            state.coalesced += 1;
+            return true;
+        }
+        if state.order.len() >= self.capacity {
+            state.dropped += 1;
+            return false;
+        }
+
+        state.queued.insert(path.clone());
+        state.order.push_back(path);
+        self.changed.notify_all();
+
+        true
+    }
+
+    /// Waits for a path to scan and a free slot to scan it.
+    pub fn pop(self: &Arc<Self>) -> Work {
+        let mut state = self.state.lock().unwrap();
+
+        loop {
+            if state.in_flight < self.max_in_flight {
+                if let Some(path) = state.order.pop_front() {
+                    state.queued.remove(&path);
+                    state.in_flight += 1;
+                    return Work {
+                        path,
+                        queue: self.clone(),
+                    };
+                }
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    // release the slot of a path once it's been scanned
+    fn done(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        state.in_flight -= 1;
+        self.changed.notify_all();
+    }
+
+    pub fn stats(&self) -> Stats {
+        let state = self.state.lock().unwrap();
+
+        Stats {
+            depth: state.order.len(),
+            in_flight: state.in_flight,
+            coalesced: state.coalesced,
+            dropped: state.dropped,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn coalesces_queued_paths() {
+        let queue = Arc::new(WorkQueue::new(10, 1));
+
+        assert!(queue.push(PathBuf::from("/a")));
+        assert!(queue.push(PathBuf::from("/b")));
+        assert!(queue.push(PathBuf::from("/a")));
+
+        let stats = queue.stats();
+        assert_eq!(stats.depth, 2);
+        assert_eq!(stats.coalesced, 1);
+
+        // once taken from the queue, the path can be queued again
+        let work = queue.pop();
+        assert_eq!(work.path, PathBuf::from("/a"));
+        assert!(queue.push(PathBuf::from("/a")));
+        assert_eq!(queue.stats().depth, 2);
+        assert_eq!(queue.stats().coalesced, 1);
+    }
+
+    #[test]
+    fn drops_paths_when_full() {
+        let queue = WorkQueue::new(2, 1);
+
+        assert!(queue.push(PathBuf::from("/a")));
+        assert!(queue.push(PathBuf::from("/b")));
+        assert!(!queue.push(PathBuf::from("/c")));
+        // already queued paths are coalesced, not dropped
+        assert!(queue.push(PathBuf::from("/b")));
+
+        let stats = queue.stats();
+        assert_eq!(stats.depth, 2);
+        assert_eq!(stats.dropped, 1);
+        assert_eq!(stats.coalesced, 1);
+    }
+
+    #[test]
+    fn bounds_paths_in_flight() {
+        let queue = Arc::new(WorkQueue::new(10, 2));
+
+        for path in ["/a", "/b", "/c"] {
+            queue.push(PathBuf::from(path));
+        }
+
+        let first = queue.pop();
+        let _second = queue.pop();
+        assert_eq!(queue.stats().in_flight, 2);
+
+        let (tx, rx) = mpsc::channel();
+        let waiting = queue.clone();
+        let handle = thread::spawn(move || {
+            let work = waiting.pop();
+            tx.send(work.path.clone()).unwrap();
+        });
+
+        // no free slot until a path is done
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+        drop(first);
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            PathBuf::from("/c")
+        );
+
+        handle.join().unwrap();
+        assert_eq!(queue.stats().in_flight, 1);
+        assert_eq!(queue.stats().depth, 0);
+    }
+}
@@ -0,0 +1,221 @@
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::actions::Outcome;
+use crate::engine::{self, Detection};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Human readable log lines.
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Summary {
+    pub scanned: u32,
+    pub detected: u32,
+    pub errors: u32,
+    #[serde(
+        rename = "scan_duration",
+        serialize_with = "engine::serialize_duration"
+    )]
+    pub elapsed: Duration,
+}
+
+/// Receives the results of scan and monitor jobs, possibly from several worker threads.
+pub trait Reporter: Send + Sync {
+    fn detection(&self, detection: &Detection);
+    /// A clean file, only reported with its hashes when every scanned file is hashed.
+    fn scanned(&self, _detection: &Detection) {}
+    fn error(&self, path: &Path, error: &str);
+    fn action(&self, detection: &Detection, outcome: &Outcome);
+    fn summary(&self, summary: &Summary);
+}
+
+pub fn create(
+    format: &OutputFormat,
+    output_file: Option<&String>,
+) -> Result<Box<dyn Reporter>, String> {
+    match format {
+        OutputFormat::Text => {
+            if output_file.is_some() {
+                return Err("--output-file can only be used with --output json".to_string());
+            }
+            Ok(Box::new(TextReporter {}))
+        }
+        OutputFormat::Json => {
+            let output: Box<dyn Write + Send> = match output_file {
+                Some(path) => Box::new(
+                    OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(path)
+                        .map_err(|e| format!("can't open {}: {:?}", path, e))?,
+                ),
+                None => Box::new(io::stdout()),
+            };
+            Ok(Box::new(JsonReporter {
+                output: Mutex::new(output),
+            }))
+        }
+    }
+}
+
+/// Reports results as log lines.
+pub struct TextReporter {}
+
+impl TextReporter {
+    fn details(&self, detection: &Detection) {
+        if let Some(process) = &detection.process {
+            log::warn!(
+                "  pid={} exe={:?} cmdline={:?}",
+                process.pid,
+                process.exe.as_deref().unwrap_or_else(|| Path::new("?")),
+                &process.cmdline
+            );
+        }
+        if let Some(hashes) = &detection.hashes {
+            log::warn!(
+                "  md5={} sha1={} sha256={}",
+                &hashes.md5,
+                &hashes.sha1,
+                &hashes.sha256
+            );
+        }
+        for rule in &detection.matches {
+            log::warn!("  {}", rule);
+        }
+    }
+}
+
+impl Reporter for TextReporter {
+    fn scanned(&self, detection: &Detection) {
+        if let Some(hashes) = &detection.hashes {
+            log::info!(
+                "{:?} is clean, md5={} sha1={} sha256={}",
+                &detection.path,
+                &hashes.md5,
+                &hashes.sha1,
+                &hashes.sha256
+            );
+        }
+    }
+
+    fn detection(&self, detection: &Detection) {
+        log::warn!(
+            "!!! MALWARE DETECTION: '{:?}' detected as '{:?}'",
+            &detection.path,
+            detection.tags.join(", ")
+        );
+        self.details(detection);
+        for member in &detection.members {
+            log::warn!(
+                "  '{:?}' detected as '{:?}'",
+                &member.path,
+                member.tags.join(", ")
+            );
+            self.details(member);
+        }
+    }
+
+    fn error(&self, _path: &Path, error: &str) {
+        log::debug!("{:?}", error)
+    }
+
+    fn action(&self, detection: &Detection, outcome: &Outcome) {
+        if outcome.success {
+            log::warn!(
+                "{} action for {:?}: {}",
+                &outcome.action,
+                &detection.path,
+                &outcome.message
+            );
+        } else {
+            log::error!(
+                "{} action for {:?} failed: {}",
+                &outcome.action,
+                &detection.path,
+                &outcome.message
+            );
+        }
+    }
+
+    // printed regardless of the log level, on stderr so that it's not mixed with the output
+    // of commands
+    fn summary(&self, summary: &Summary) {
+        eprintln!(
+            "{} files scanned in {:?}, {} positive detections, {} errors",
+            summary.scanned, summary.elapsed, summary.detected, summary.errors
+        );
+    }
+}
+
+/// Reports results as JSON Lines, one object per event.
+pub struct JsonReporter {
+    output: Mutex<Box<dyn Write + Send>>,
+}
+
+impl JsonReporter {
+    fn write(&self, kind: &str, data: impl Serialize) {
+        let mut record = match serde_json::to_value(data) {
+            Ok(record) => record,
+            Err(e) => {
+                log::error!("can't serialize {} record: {:?}", kind, e);
+                return;
+            }
+        };
+        if let Some(fields) = record.as_object_mut() {
+            fields.insert("type".to_string(), json!(kind));
+            fields.insert(
+                "timestamp".to_string(),
+                json!(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)),
+            );
+        }
+
+        // lock the output so that lines written by concurrent workers don't interleave
+        let mut output = self.output.lock().unwrap();
+        if let Err(e) = writeln!(output, "{}", record).and_then(|_| output.flush()) {
+            log::error!("can't write {} record: {:?}", kind, e);
+        }
+    }
+}
+
+impl Reporter for JsonReporter {
+    fn detection(&self, detection: &Detection) {
+        self.write("detection", detection);
+    }
+
+    fn scanned(&self, detection: &Detection) {
+        self.write("scanned", detection);
+    }
+
+    fn error(&self, path: &Path, error: &str) {
+        self.write("error", json!({ "path": path, "error": error }));
+    }
+
+    fn action(&self, detection: &Detection, outcome: &Outcome) {
+        self.write(
+            "action",
+            json!({
+                "path": &detection.path,
+                "action": &outcome.action,
+                "success": outcome.success,
+                "message": &outcome.message,
+            }),
+        );
+    }
+
+    fn summary(&self, summary: &Summary) {
+        self.write("summary", summary);
+    }
+}
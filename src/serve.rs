@@ -0,0 +1,321 @@
+use std::io::{Cursor, Read};
+use std::net::ToSocketAddrs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+
+use serde::Deserialize;
+use serde_json::json;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::engine::{Detection, Engine, Error};
+use crate::report::Reporter;
+
+// prefix of listen addresses to be parsed as Unix socket paths
+const UNIX_PREFIX: &str = "unix:";
+
+// label of uploaded data scanned without a name
+const DEFAULT_NAME: &str = "upload";
+
+type JsonResponse = Response<Cursor<Vec<u8>>>;
+
+#[derive(Deserialize)]
+struct PathRequest {
+    path: PathBuf,
+}
+
+fn respond(status: u16, body: serde_json::Value) -> JsonResponse {
+    Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(
+            Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+                .expect("invalid header"),
+        )
+}
+
+fn error(status: u16, message: &str) -> JsonResponse {
+    respond(status, json!({ "error": message }))
+}
+
+// clear the matched data of the detection and its archive members, the server can read
+// files the client can't
+fn strip_snippets(detection: &mut Detection) {
+    for rule in &mut detection.matches {
+        for string in &mut rule.strings {
+            string.snippet.clear();
+        }
+    }
+    detection.members.iter_mut().for_each(strip_snippets);
+}
+
+// decode a percent-encoded query string value
+fn decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (b'+', _) => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+// value of a query string parameter
+fn query_param(query: &str, name: &str) -> Option<String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| decode(value))
+}
+
+// whether the request body is declared as JSON, so that browsers can't send it cross-origin
+// without a preflight
+fn is_json(request: &Request) -> bool {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Content-Type"))
+        .and_then(|header| header.value.as_str().split(';').next())
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"))
+}
+
+// read the request body, failing if it's bigger than the limit
+fn read_body(request: &mut Request, max_size: u64) -> Result<Vec<u8>, JsonResponse> {
+    if request
+        .body_length()
+        .is_some_and(|length| length as u64 > max_size)
+    {
+        return Err(error(413, "request body too large"));
+    }
+
+    let mut body = vec![];
+    // read one byte more than the limit to tell if it's exceeded, the length can be unknown
+    request
+        .as_reader()
+        .take(max_size.saturating_add(1))
+        .read_to_end(&mut body)
+        .map_err(|e| error(400, &format!("can't read request body: {:?}", e)))?;
+
+    if body.len() as u64 > max_size {
+        return Err(error(413, "request body too large"));
+    }
+
+    Ok(body)
+}
+
+/// Scans the data and files submitted by local clients over HTTP.
+struct Api {
+    engine: Arc<Engine>,
+    reporter: Arc<dyn Reporter>,
+    max_body_size: u64,
+    allow_path_scan: bool,
+}
+
+impl Api {
+    fn handle(&self, mut request: Request) {
+        let (path, query) = match request.url().split_once('?') {
+            Some((path, query)) => (path.to_string(), query.to_string()),
+            None => (request.url().to_string(), String::new()),
+        };
+
+        log::debug!("{} {}", request.method(), request.url());
+
+        let response = match (request.method(), path.as_str()) {
+            (Method::Get, "/health") => respond(200, json!({ "status": "ok" })),
+            (Method::Get, "/rules") => self.rules(),
+            (Method::Post, "/scan") => match read_body(&mut request, self.max_body_size) {
+                Ok(body) => {
+                    let name =
+                        query_param(&query, "name").unwrap_or_else(|| DEFAULT_NAME.to_string());
+                    self.scan_bytes(&body, name)
+                }
+                Err(response) => response,
+            },
+            (Method::Post, "/scan/path") if !self.allow_path_scan => {
+                error(403, "path scans are disabled, see --allow-path-scan")
+            }
+            (Method::Post, "/scan/path") if !is_json(&request) => {
+                error(415, "the request must be application/json")
+            }
+            (Method::Post, "/scan/path") => match read_body(&mut request, self.max_body_size) {
+                Ok(body) => self.scan_path(&body),
+                Err(response) => response,
+            },
+            (_, "/health" | "/rules" | "/scan" | "/scan/path") => error(405, "method not allowed"),
+            _ => error(404, "not found"),
+        };
+
+        if let Err(e) = request.respond(response) {
+            log::debug!("can't send response: {:?}", e);
+        }
+    }
+
+    fn rules(&self) -> JsonResponse {
+        let rules = self.engine.loaded_rules();
+
+        respond(
+            200,
+            json!({
+                "path": self.engine.rules_path(),
+                "fingerprint": self.engine.fingerprint(),
+                "count": rules.len(),
+                "rules": rules,
+            }),
+        )
+    }
+
+    fn scan_bytes(&self, data: &[u8], name: String) -> JsonResponse {
+        let res = self.engine.scan_bytes(data, name);
+        self.report(res, false)
+    }
+
+    fn scan_path(&self, body: &[u8]) -> JsonResponse {
+        let path = match serde_json::from_slice::<PathRequest>(body) {
+            Ok(request) => request.path,
+            Err(e) => return error(400, &format!("invalid request: {}", e)),
+        };
+
+        if !path.is_file() {
+            return error(400, &format!("{:?} is not a file", path));
+        }
+
+        let res = self.engine.scan(&path);
+        self.report(res, true)
+    }
+
+    fn report(&self, mut res: Detection, strip: bool) -> JsonResponse {
+        if let Some(error) = &res.error {
+            self.reporter.error(&res.path, error);
+        } else if res.detected {
+            self.reporter.detection(&res);
+        }
+
+        if strip {
+            strip_snippets(&mut res);
+        }
+
+        match serde_json::to_value(&res) {
+            Ok(value) => respond(200, value),
+            Err(e) => error(500, &format!("can't serialize detection: {}", e)),
+        }
+    }
+}
+
+fn bind(listen: &str, allow_remote: bool) -> Result<Server, Error> {
+    match listen.strip_prefix(UNIX_PREFIX) {
+        #[cfg(unix)]
+        Some(path) => {
+            use std::fs;
+            use std::os::unix::fs::FileTypeExt;
+            use std::path::Path;
+
+            // remove the socket left by a previous run
+            if fs::metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+                fs::remove_file(path).map_err(|e| format!("can't remove {}: {:?}", path, e))?;
+            }
+
+            Server::http_unix(Path::new(path))
+        }
+        #[cfg(not(unix))]
+        Some(_) => return Err("unix sockets are not supported on this platform".to_string()),
+        None => {
+            let addrs: Vec<_> = listen
+                .to_socket_addrs()
+                .map_err(|e| format!("invalid address {}: {}", listen, e))?
+                .collect();
+
+            if addrs.iter().any(|addr| !addr.ip().is_loopback()) {
+                if !allow_remote {
+                    return Err(format!(
+                        "the API has no authentication, refusing to listen on {} which is not loopback without --allow-remote",
+                        listen
+                    ));
+                }
+                log::warn!("the API has no authentication and listens on {}", listen);
+            }
+
+            Server::http(&addrs[..])
+        }
+    }
+    .map_err(|e| format!("can't listen on {}: {}", listen, e))
+}
+
+/// Serves the HTTP API on a TCP address or, if prefixed with 'unix:', on a Unix socket,
+/// handling up to as many requests at a time as the number of workers. TCP addresses
+/// have to be loopback unless allow_remote is set, and files are only scanned by path
+/// with allow_path_scan.
+pub fn serve(
+    engine: Arc<Engine>,
+    listen: &str,
+    workers: usize,
+    max_body_size: u64,
+    allow_path_scan: bool,
+    allow_remote: bool,
+    reporter: Arc<dyn Reporter>,
+) -> Result<(), Error> {
+    let server = Arc::new(bind(listen, allow_remote)?);
+    let api = Arc::new(Api {
+        engine,
+        reporter,
+        max_body_size,
+        allow_path_scan,
+    });
+
+    log::info!("serving on {} with {} workers ...", listen, workers);
+
+    let handles: Vec<_> = (0..workers.max(1))
+        .map(|_| {
+            let server = server.clone();
+            let api = api.clone();
+
+            thread::spawn(move || loop {
+                match server.recv() {
+                    Ok(request) => api.handle(request),
+                    Err(e) => {
+                        log::error!("can't receive request: {:?}", e);
+                        break;
+                    }
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        let _ = handle.join();
+    }
+
+    Err(format!("stopped serving on {}", listen))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_remote_addresses_by_default() {
+        assert!(bind("0.0.0.0:0", false).is_err());
+        assert!(bind("127.0.0.1:0", false).is_ok());
+        assert!(bind("localhost:0", false).is_ok());
+        assert!(bind("0.0.0.0:0", true).is_ok());
+    }
+}